@@ -0,0 +1,75 @@
+use std::hint::black_box;
+
+use chrono::Utc;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+use producer::models::get_envelopes_with_format;
+use producer::models::get_msg_bytes;
+use producer::models::payload_format::PayloadFormat;
+use producer::models::processing_config::ProcessingConfig;
+use producer::models::topic::Topic;
+
+// to run it on a Raspberry Pi-class target see the 'bench' target in the Makefile
+
+const DEVICE_UUID: &str = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+
+fn single_payload() -> Vec<u8> {
+    format!(
+        r#"{{"deviceUuid":"{DEVICE_UUID}","featureUuid":"41cb3f47-894c-45e9-90d9-a4d4de903896","apiToken":"473a4861-632b-4915-b01e-cf1d418966c6","payload":{{"value":21.5}},"unit":"°C","timestamp":1766658600}}"#
+    )
+    .into_bytes()
+}
+
+fn multi_payload() -> Vec<u8> {
+    format!(
+        r#"{{"deviceUuid":"{DEVICE_UUID}","apiToken":"473a4861-632b-4915-b01e-cf1d418966c6","timestamp":1766658600,"readings":[{{"featureName":"temperature","featureUuid":"41cb3f47-894c-45e9-90d9-a4d4de903896","payload":{{"value":21.5}}}},{{"featureName":"humidity","featureUuid":"7c1ea1c4-1bd6-4a4a-b8a3-4e5b0f6d2c11","payload":{{"value":48.0}}}},{{"featureName":"airpressure","featureUuid":"c0a1d9e2-5f3b-4a8e-9d7c-2b6e1f4a3c58","payload":{{"value":1013.2}}}}]}}"#
+    )
+    .into_bytes()
+}
+
+// previous hot path: payload copied into a String, one new buffer for each outgoing message
+fn copy_and_allocate(topic: &Topic, payload: &[u8], config: &ProcessingConfig) -> usize {
+    let payload_str = String::from_utf8(payload.to_vec()).unwrap();
+    get_msg_bytes(topic, &payload_str, Utc::now(), config)
+        .into_iter()
+        .map(|result| result.unwrap().len())
+        .sum()
+}
+
+// current hot path: strings borrowed from the payload, outgoing messages encoded in a reused buffer
+fn borrow_and_reuse(topic: &Topic, payload: &[u8], config: &ProcessingConfig, buffer: &mut Vec<u8>) -> usize {
+    let payload_str = std::str::from_utf8(payload).unwrap();
+    get_envelopes_with_format(topic, payload_str.as_bytes(), PayloadFormat::Json, Utc::now(), config)
+        .into_iter()
+        .map(|result| {
+            result
+                .unwrap()
+                .encode_into(config.output_codec, config.schema_version, buffer);
+            buffer.len()
+        })
+        .sum()
+}
+
+fn bench_hot_path(c: &mut Criterion) {
+    let config = ProcessingConfig::default();
+    let cases = [
+        ("single", "temperature", single_payload()),
+        ("multi", "multi", multi_payload()),
+    ];
+    for (name, feature_name, payload) in cases {
+        let topic = Topic::new(&format!("sensors/{DEVICE_UUID}/{feature_name}"));
+        let mut group = c.benchmark_group(format!("hot_path_{name}"));
+        group.throughput(Throughput::Bytes(payload.len() as u64));
+        group.bench_function("copy_and_allocate", |b| {
+            b.iter(|| copy_and_allocate(black_box(&topic), black_box(&payload), &config))
+        });
+        let mut buffer = Vec::with_capacity(1024);
+        group.bench_function("borrow_and_reuse", |b| {
+            b.iter(|| borrow_and_reuse(black_box(&topic), black_box(&payload), &config, &mut buffer))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_hot_path);
+criterion_main!(benches);
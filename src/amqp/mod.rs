@@ -1,21 +1,35 @@
 use std::string::String;
 use std::time::Duration;
 
-use lapin::publisher_confirm::PublisherConfirm;
+use chrono::Utc;
+use futures::StreamExt;
+use lapin::publisher_confirm::{Confirmation, PublisherConfirm};
 use lapin::{
-    BasicProperties, Channel, Connection, ConnectionProperties, Queue, RecoveryConfig,
-    options::{BasicPublishOptions, QueueDeclareOptions},
-    types::FieldTable,
+    BasicProperties, Channel, Connection, ConnectionProperties, Event, Queue, RecoveryConfig,
+    options::{BasicPublishOptions, ConfirmSelectOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable},
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
+use crate::config::secret::read_secret_file;
 use crate::errors::amqp_error::AmqpError;
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::redact::redact_uri;
 
 pub struct AmqpClient {
     connecting: bool,
     connection: Option<Connection>,
     channel: Option<Channel>,
     queue: Option<Queue>,
+    extra_queue_names: Vec<String>,
+    content_type: String,
+    // optional file with the AMQP URI, read again on each connection
+    amqp_uri_file: String,
+    // publishes, confirms and reconnections, see `set_metrics`
+    metrics: Option<Metrics>,
+    // connectivity and last successful publish, see `set_health`
+    health: Option<Health>,
     pub amqp_uri: String,
     pub amqp_queue_name: String,
 }
@@ -27,6 +41,11 @@ impl AmqpClient {
             connection: None,
             channel: None,
             queue: None,
+            extra_queue_names: vec![],
+            content_type: String::from("application/json"),
+            amqp_uri_file: String::new(),
+            metrics: None,
+            health: None,
             amqp_uri,
             amqp_queue_name,
         }
@@ -34,34 +53,128 @@ impl AmqpClient {
 
     // init or re-init the amqp client trying to connect in a loop until success
     pub async fn connect_with_retry_loop(&mut self) {
-        info!(target: "app", "connect_with_retry_loop - trying to connect to amqp_uri={} with queue={}", &self.amqp_uri, &self.amqp_queue_name);
+        info!(target: "app", "connect_with_retry_loop - trying to connect to amqp_uri={} with queue={}", redact_uri(&self.amqp_uri), &self.amqp_queue_name);
         self.connecting = true;
+        self.set_health(false);
+        if self.connection.is_some()
+            && let Some(metrics) = &self.metrics
+        {
+            metrics.inc_amqp_reconnect();
+        }
         self.create_connection().await;
         self.create_channel().await.unwrap();
         self.declare_queue().await.unwrap();
         self.connecting = false;
+        self.set_health(true);
         info!(target: "app", "connect_with_retry_loop - AMQP connection done!");
     }
 
+    // additional queue declared on connection, so messages can be published also there.
+    // You must call this method before connect_with_retry_loop()
+    pub fn add_queue(&mut self, queue_name: String) {
+        self.extra_queue_names.push(queue_name);
+    }
+
+    // content_type of messages published with publish_message()
+    pub fn set_content_type(&mut self, content_type: String) {
+        self.content_type = content_type;
+    }
+
+    // file with the AMQP URI, like a Docker or Kubernetes secret, read again on each connection
+    // so rotated credentials are used on reconnection
+    pub fn set_uri_file(&mut self, amqp_uri_file: String) {
+        self.amqp_uri_file = amqp_uri_file;
+    }
+
+    // counts publishes, publisher confirms and reconnections.
+    // Publisher confirms are enabled on the channel only to count them, so call it only if metrics are served.
+    // You must call this method before connect_with_retry_loop()
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    // reports the connection state and successful publishes to /healthz and /readyz
+    pub fn set_health_check(&mut self, health: Health) {
+        self.health = Some(health);
+    }
+
+    fn set_health(&self, connected: bool) {
+        if let Some(health) = &self.health {
+            health.set_amqp_connected(connected, Utc::now());
+        }
+    }
+
+    // counts the confirmation of a published message when the broker sends it, without waiting for it
+    pub fn track_confirm(&self, confirm: PublisherConfirm) {
+        let Some(metrics) = self.metrics.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            match confirm.await {
+                Ok(Confirmation::Ack(_)) => {
+                    metrics.inc_confirm(true);
+                }
+                Ok(Confirmation::Nack(_)) => {
+                    metrics.inc_confirm(false);
+                }
+                Ok(Confirmation::NotRequested) => {}
+                Err(err) => {
+                    error!(target: "app", "track_confirm - cannot receive AMQP publisher confirm. Err = {:?}", err);
+                }
+            }
+        });
+    }
+
     // before calling this method you must be sure that is_connected() returns true
-    pub async fn publish_message(&self, msg_byte: Vec<u8>) -> Result<PublisherConfirm, AmqpError> {
-        debug!(target: "app", "publish_message - publishing byte message to queue {}", &self.amqp_queue_name);
+    pub async fn publish_message(&self, msg_byte: &[u8]) -> Result<PublisherConfirm, AmqpError> {
+        self.publish_message_to(&self.amqp_queue_name, msg_byte, &self.content_type)
+            .await
+    }
+
+    // before calling this method you must be sure that is_connected() returns true
+    pub async fn publish_message_to(
+        &self,
+        queue_name: &str,
+        msg_byte: &[u8],
+        content_type: &str,
+    ) -> Result<PublisherConfirm, AmqpError> {
+        self.publish_message_with_header(queue_name, msg_byte, content_type, None)
+            .await
+    }
+
+    // like publish_message_to(), with an optional AMQP header as (name, value)
+    pub async fn publish_message_with_header(
+        &self,
+        queue_name: &str,
+        msg_byte: &[u8],
+        content_type: &str,
+        header: Option<(&str, &str)>,
+    ) -> Result<PublisherConfirm, AmqpError> {
+        debug!(target: "app", "publish_message_with_header - publishing byte message to queue {}", queue_name);
         if self.connecting {
-            error!(target: "app", "publish_message - cannot publish while connecting");
+            error!(target: "app", "publish_message_with_header - cannot publish while connecting");
             return Err(AmqpError::Connecting(String::from("cannot publish while connecting")));
         }
+        let mut properties = BasicProperties::default().with_content_type(content_type.into());
+        if let Some((name, value)) = header {
+            let mut headers = FieldTable::default();
+            headers.insert(name.into(), AMQPValue::LongString(value.into()));
+            properties = properties.with_headers(headers);
+        }
         let publish_result: lapin::Result<PublisherConfirm> = self
             .channel
             .as_ref()
             .unwrap()
-            .basic_publish(
-                "",
-                &self.amqp_queue_name,
-                BasicPublishOptions::default(),
-                msg_byte.as_slice(),
-                BasicProperties::default(),
-            )
+            .basic_publish("", queue_name, BasicPublishOptions::default(), msg_byte, properties)
             .await;
+        if let Some(metrics) = &self.metrics {
+            metrics.inc_publish(queue_name, publish_result.is_ok());
+        }
+        if let Some(health) = &self.health
+            && publish_result.is_ok()
+        {
+            health.record_publish(Utc::now());
+        }
         match publish_result {
             Ok(confirm) => Ok(confirm),
             Err(err) => Err(AmqpError::Publish(err)),
@@ -75,12 +188,34 @@ impl AmqpClient {
         if init_result.is_err() {
             return false;
         }
-        self.connection.as_ref().unwrap().status().connected() && self.channel.as_ref().unwrap().status().connected()
+        let connected = self.connection.as_ref().unwrap().status().connected()
+            && self.channel.as_ref().unwrap().status().connected();
+        self.set_health(connected);
+        connected
+    }
+
+    // keeps the current URI if the file cannot be read
+    fn reload_uri(&mut self) {
+        if self.amqp_uri_file.is_empty() {
+            return;
+        }
+        match read_secret_file(&self.amqp_uri_file) {
+            Ok(amqp_uri) => {
+                if amqp_uri != self.amqp_uri {
+                    info!(target: "app", "reload_uri - AMQP URI changed in {}", &self.amqp_uri_file);
+                    self.amqp_uri = amqp_uri;
+                }
+            }
+            Err(err) => {
+                warn!(target: "app", "reload_uri - cannot read AMQP URI, using the previous one. Err = {:?}", err);
+            }
+        }
     }
 
     async fn create_connection(&mut self) {
         info!(target: "app", "create_connection - creating AMQP connection...");
         self.connection = loop {
+            self.reload_uri();
             let options = ConnectionProperties::default()
                 .with_experimental_recovery_config(RecoveryConfig::full())
                 .with_executor(tokio_executor_trait::Tokio::current())
@@ -96,8 +231,18 @@ impl AmqpClient {
                 }
             };
         };
-        self.connection.as_ref().unwrap().on_error(|err| {
-            error!(target: "app", "create_connection - AMQP connection error = {:?}", err);
+        let mut events = self.connection.as_ref().unwrap().events_listener();
+        let health = self.health.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let Event::Error(err) = event {
+                    error!(target: "app", "create_connection - AMQP connection error = {:?}", err);
+                    // not ready until the next publish finds the connection recovered
+                    if let Some(health) = &health {
+                        health.set_amqp_connected(false, Utc::now());
+                    }
+                }
+            }
         });
     }
 
@@ -115,6 +260,11 @@ impl AmqpClient {
             match self.connection.as_ref().unwrap().create_channel().await {
                 Ok(channel) => {
                     info!(target: "app", "create_channel - AMQP channel created");
+                    if self.metrics.is_some()
+                        && let Err(err) = channel.confirm_select(ConfirmSelectOptions::default()).await
+                    {
+                        warn!(target: "app", "create_channel - cannot enable AMQP publisher confirms. Err = {:?}", err);
+                    }
                     break Some(channel);
                 }
                 Err(err) => {
@@ -137,29 +287,32 @@ impl AmqpClient {
         // instead of the verbose syntax
         // if let Err(err) = init_result { return Err(err); }
         init_result?;
-        self.queue = loop {
+        self.queue = Some(self.declare_queue_with_name(&self.amqp_queue_name).await);
+        for queue_name in self.extra_queue_names.iter() {
+            self.declare_queue_with_name(queue_name).await;
+        }
+        Ok(())
+    }
+
+    async fn declare_queue_with_name(&self, queue_name: &str) -> Queue {
+        loop {
             match self
                 .channel
                 .as_ref()
                 .unwrap()
-                .queue_declare(
-                    &self.amqp_queue_name,
-                    QueueDeclareOptions::default(),
-                    FieldTable::default(),
-                )
+                .queue_declare(queue_name, QueueDeclareOptions::default(), FieldTable::default())
                 .await
             {
-                Ok(channel) => {
-                    info!(target: "app", "declare_queue - AMQP queue created");
-                    break Some(channel);
+                Ok(queue) => {
+                    info!(target: "app", "declare_queue_with_name - AMQP queue {} created", queue_name);
+                    break queue;
                 }
                 Err(err) => {
-                    error!(target: "app", "declare_queue - cannot create AMQP queue, retrying in 10 seconds. Err = {:?}", err);
+                    error!(target: "app", "declare_queue_with_name - cannot create AMQP queue {}, retrying in 10 seconds. Err = {:?}", queue_name, err);
                     tokio::time::sleep(Duration::from_millis(10000)).await;
                 }
             };
-        };
-        Ok(())
+        }
     }
 
     fn is_initialized(&self, check_connection: bool, check_channel: bool, check_queue: bool) -> Result<(), AmqpError> {
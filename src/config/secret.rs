@@ -0,0 +1,89 @@
+use std::fs::{metadata, read_to_string};
+
+use tracing::warn;
+
+use crate::errors::secret_error::SecretError;
+
+// reads a secret from a file, like Docker secrets and Kubernetes secret volumes.
+// The content is trimmed, because these files often end with a new line.
+// Files writable by group or others are refused, because anyone could replace the credentials.
+pub fn read_secret_file(path: &str) -> Result<String, SecretError> {
+    let meta = metadata(path).map_err(|err| SecretError::Read(path.to_string(), err))?;
+    check_permissions(path, &meta)?;
+    let content = read_to_string(path).map_err(|err| SecretError::Read(path.to_string(), err))?;
+    let secret = content.trim();
+    if secret.is_empty() {
+        return Err(SecretError::Empty(path.to_string()));
+    }
+    Ok(secret.to_string())
+}
+
+// value of a secret defined by an env var or by a file, with the file taking precedence
+pub fn resolve_secret(value: &str, file: &str) -> Result<String, SecretError> {
+    if file.is_empty() {
+        Ok(value.to_string())
+    } else {
+        read_secret_file(file)
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &str, meta: &std::fs::Metadata) -> Result<(), SecretError> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = meta.permissions().mode() & 0o777;
+    if mode & 0o022 != 0 {
+        return Err(SecretError::Permissions(path.to_string(), mode));
+    }
+    // the default mode of Kubernetes secret volumes, so it's allowed
+    if mode & 0o004 != 0 {
+        warn!(target: "app", "check_permissions - secret file {} is readable by others (mode {:o})", path, mode);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &str, _meta: &std::fs::Metadata) -> Result<(), SecretError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::secret::{read_secret_file, resolve_secret};
+    use pretty_assertions::assert_eq;
+    use std::fs::{Permissions, remove_file, set_permissions, write};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn check_read_secret_file() {
+        let path = std::env::temp_dir().join("check_read_secret_file.txt");
+        let path_str = path.to_str().unwrap();
+        write(&path, "  s3cret\n").unwrap();
+        set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_secret_file(path_str).unwrap(), "s3cret");
+        assert_eq!(resolve_secret("from-env", path_str).unwrap(), "s3cret");
+        assert_eq!(resolve_secret("from-env", "").unwrap(), "from-env");
+
+        // readable by others, like Kubernetes secret volumes
+        set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(read_secret_file(path_str).unwrap(), "s3cret");
+
+        set_permissions(&path, Permissions::from_mode(0o666)).unwrap();
+        assert_eq!(
+            read_secret_file(path_str).err().unwrap().to_string(),
+            format!("secret file {} is writable by group or others (mode 666)", path_str)
+        );
+
+        write(&path, "\n").unwrap();
+        set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(
+            read_secret_file(path_str).err().unwrap().to_string(),
+            format!("secret file {} is empty", path_str)
+        );
+        remove_file(&path).unwrap();
+
+        assert_eq!(
+            read_secret_file(path_str).err().unwrap().to_string(),
+            format!("cannot read secret file {}", path_str)
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+use crate::errors::device_registry_error::DeviceRegistryError;
+use crate::errors::rate_limit_error::RateLimitError;
+use crate::errors::registry_error::RegistryError;
+use crate::errors::route_error::RouteError;
+use crate::errors::topic_error::TopicError;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Registry(#[from] RegistryError),
+    #[error(transparent)]
+    DeviceRegistry(#[from] DeviceRegistryError),
+    #[error(transparent)]
+    RateLimit(#[from] RateLimitError),
+    #[error(transparent)]
+    Route(#[from] RouteError),
+    #[error(transparent)]
+    Topic(#[from] TopicError),
+    #[error("API_TOKEN_HASH_KEY is required by {0}")]
+    MissingHashKey(String),
+    #[error("QUARANTINE_RELEASE_KEY is required by {0}")]
+    MissingReleaseKey(String),
+    #[error("DEVICE_REGISTRY_FILE is required by {0}")]
+    MissingDeviceRegistry(String),
+    #[error("BATCH_MODE=envelope requires schema version 2, but {0} uses version 1")]
+    BatchSchemaVersion(String),
+}
@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("invalid JSON payload: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid CBOR payload: {0}")]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+    #[error("invalid MessagePack payload: {0}")]
+    MessagePack(#[from] rmp_serde::decode::Error),
+}
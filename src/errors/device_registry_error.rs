@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum DeviceRegistryError {
+    #[error("cannot read device registry file {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("cannot parse device registry file {0}")]
+    Parse(String, #[source] serde_json::Error),
+    #[error("invalid device {0} in device registry: {1}")]
+    InvalidDevice(String, String),
+}
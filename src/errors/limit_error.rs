@@ -0,0 +1,26 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LimitError {
+    #[error("payload of {0} bytes exceeds max {1}")]
+    PayloadTooLarge(usize, usize),
+    #[error("nesting exceeds max depth {0}")]
+    TooDeep(usize),
+    #[error("string of {0} bytes exceeds max {1}")]
+    StringTooLong(usize, usize),
+    #[error("unknown field {0}")]
+    UnknownField(String),
+}
+
+impl LimitError {
+    // short name used as counter label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LimitError::PayloadTooLarge(_, _) => "payload_too_large",
+            LimitError::TooDeep(_) => "too_deep",
+            LimitError::StringTooLong(_, _) => "string_too_long",
+            LimitError::UnknownField(_) => "unknown_field",
+        }
+    }
+}
@@ -1,5 +1,11 @@
+use serde_json::error::Category;
 use thiserror::Error;
 
+use crate::errors::decode_error::DecodeError;
+use crate::errors::limit_error::LimitError;
+use crate::errors::topic_error::TopicError;
+use crate::models::sensor_registry::ValueType;
+
 // custom error, based on 'thiserror' library
 #[derive(Error, Debug)]
 pub enum MessageError {
@@ -7,4 +13,221 @@ pub enum MessageError {
     EmptyMessageError,
     #[error("Cannot publish message error")]
     PublishMessageError,
+    #[error("Rejected {feature} reading error: {reason}")]
+    Rejected { feature: String, reason: RejectReason },
+    #[error("Cannot decode message error: {0}")]
+    InvalidPayload(DecodeError),
+    #[error("Unknown feature {0} error")]
+    UnknownFeature(String),
+    #[error("Invalid UTF-8 payload error: {0}")]
+    InvalidUtf8(#[source] std::str::Utf8Error),
+    #[error("Invalid JSON at line {line} column {column} error: {reason}")]
+    InvalidJson { line: usize, column: usize, reason: String },
+    #[error("Type mismatch error: {0}")]
+    TypeMismatch(String),
+    #[error("Missing field {0} error")]
+    MissingField(String),
+    #[error("Invalid topic error: {0}")]
+    InvalidTopic(#[from] TopicError),
+    #[error("Payload limit exceeded error: {0}")]
+    LimitExceeded(#[from] LimitError),
+}
+
+impl MessageError {
+    // short name used as counter label and in the rejects queue
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MessageError::EmptyMessageError => "empty_message",
+            MessageError::PublishMessageError => "publish",
+            MessageError::Rejected { reason, .. } => reason.kind(),
+            MessageError::InvalidPayload(_) => "invalid_payload",
+            MessageError::UnknownFeature(_) => "unknown_feature",
+            MessageError::InvalidUtf8(_) => "invalid_utf8",
+            MessageError::InvalidJson { .. } => "invalid_json",
+            MessageError::TypeMismatch(_) => "type_mismatch",
+            MessageError::MissingField(_) => "missing_field",
+            MessageError::InvalidTopic(_) => "invalid_topic",
+            MessageError::LimitExceeded(_) => "limit_exceeded",
+        }
+    }
+
+    // serde reports missing fields with the message "missing field `name`"
+    fn missing_field(message: &str) -> Option<String> {
+        let start = message.find("missing field `")? + "missing field `".len();
+        let end = start + message[start..].find('`')?;
+        Some(message[start..end].to_string())
+    }
+}
+
+impl From<serde_json::Error> for MessageError {
+    fn from(err: serde_json::Error) -> Self {
+        match err.classify() {
+            Category::Data => match MessageError::missing_field(&err.to_string()) {
+                Some(field) => MessageError::MissingField(field),
+                None => MessageError::TypeMismatch(err.to_string()),
+            },
+            Category::Syntax | Category::Eof | Category::Io => {
+                // remove the position, because it's already in line and column
+                let message = err.to_string();
+                let reason = message.split(" at line ").next().unwrap_or_default().to_string();
+                MessageError::InvalidJson {
+                    line: err.line(),
+                    column: err.column(),
+                    reason,
+                }
+            }
+        }
+    }
+}
+
+impl From<DecodeError> for MessageError {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::Json(err) => MessageError::from(err),
+            err => match MessageError::missing_field(&err.to_string()) {
+                Some(field) => MessageError::MissingField(field),
+                None => MessageError::InvalidPayload(err),
+            },
+        }
+    }
+}
+
+// reason of a reading rejected by validation rules
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    #[error("value is not of type {0}")]
+    TypeMismatch(ValueType),
+    #[error("value is not a finite number")]
+    NotFinite,
+    #[error("value {0} is lower than min {1}")]
+    BelowMin(f64, f64),
+    #[error("value {0} is greater than max {1}")]
+    AboveMax(f64, f64),
+    #[error("value {0} is not allowed")]
+    NotAllowed(String),
+    #[error("timestamp is {0} seconds in the future")]
+    TimestampSkew(i64),
+    #[error("unit {0} cannot be converted")]
+    UnknownUnit(String),
+    #[error("batch of {0} values exceeds max size {1}")]
+    BatchTooLarge(usize, usize),
+    #[error("timestamp is {0} seconds older than allowed")]
+    TooOld(i64),
+    #[error("deviceUuid {1} doesn't match device id {0} of the topic")]
+    DeviceMismatch(String, String),
+    #[error("device {0} is not registered")]
+    UnknownDevice(String),
+    #[error("API token is not valid for device {0}")]
+    InvalidToken(String),
+    #[error("feature {0} is not allowed for the device")]
+    FeatureNotAllowed(String),
+    #[error("reading of device {0} is not signed")]
+    Unsigned(String),
+    #[error("signature is not valid for device {0}")]
+    InvalidSignature(String),
+    #[error("nonce {0} was already used")]
+    ReplayedNonce(String),
+    #[error("signed timestamp is {0} seconds away from the receive time")]
+    SignatureExpired(i64),
+    #[error("{0} rate limit exceeded")]
+    RateLimited(&'static str),
+    #[error("device {0} is quarantined")]
+    Quarantined(String),
+}
+
+impl RejectReason {
+    // short name used as counter label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RejectReason::TypeMismatch(_) => "type_mismatch",
+            RejectReason::NotFinite => "not_finite",
+            RejectReason::BelowMin(_, _) => "below_min",
+            RejectReason::AboveMax(_, _) => "above_max",
+            RejectReason::NotAllowed(_) => "not_allowed",
+            RejectReason::TimestampSkew(_) => "timestamp_skew",
+            RejectReason::UnknownUnit(_) => "unknown_unit",
+            RejectReason::BatchTooLarge(_, _) => "batch_too_large",
+            RejectReason::TooOld(_) => "too_old",
+            RejectReason::DeviceMismatch(_, _) => "device_mismatch",
+            RejectReason::UnknownDevice(_) => "unknown_device",
+            RejectReason::InvalidToken(_) => "invalid_token",
+            RejectReason::FeatureNotAllowed(_) => "feature_not_allowed",
+            RejectReason::Unsigned(_) => "unsigned",
+            RejectReason::InvalidSignature(_) => "invalid_signature",
+            RejectReason::ReplayedNonce(_) => "replayed_nonce",
+            RejectReason::SignatureExpired(_) => "signature_expired",
+            RejectReason::RateLimited(_) => "rate_limited",
+            RejectReason::Quarantined(_) => "quarantined",
+        }
+    }
+
+    // true if the message was dropped to protect the broker,
+    // so it must not be published to the rejects queue either
+    pub fn is_flood(&self) -> bool {
+        matches!(self, RejectReason::RateLimited(_) | RejectReason::Quarantined(_))
+    }
+
+    // true if the reading was rejected by checks that run only after the device registry,
+    // if any, accepted the credentials of the device: values, timestamps and signatures
+    pub fn is_validation(&self) -> bool {
+        matches!(
+            self,
+            RejectReason::TypeMismatch(_)
+                | RejectReason::NotFinite
+                | RejectReason::BelowMin(_, _)
+                | RejectReason::AboveMax(_, _)
+                | RejectReason::NotAllowed(_)
+                | RejectReason::TimestampSkew(_)
+                | RejectReason::UnknownUnit(_)
+                | RejectReason::TooOld(_)
+        ) || self.is_signature()
+    }
+
+    // true if the reading was rejected by the verification of its signature
+    pub fn is_signature(&self) -> bool {
+        matches!(
+            self,
+            RejectReason::Unsigned(_)
+                | RejectReason::InvalidSignature(_)
+                | RejectReason::ReplayedNonce(_)
+                | RejectReason::SignatureExpired(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::MessageError;
+    use crate::models::notification::Notification;
+    use crate::models::payload_trait::FeaturePayload;
+    use pretty_assertions::assert_eq;
+
+    fn parse(json: &str) -> MessageError {
+        MessageError::from(
+            serde_json::from_str::<Notification<FeaturePayload>>(json)
+                .err()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn check_message_error_from_json() {
+        let err = parse("{\"deviceUuid\": \"1234\",\n 12}");
+        assert_eq!(
+            err.to_string(),
+            "Invalid JSON at line 2 column 2 error: key must be a string"
+        );
+        assert_eq!(err.kind(), "invalid_json");
+
+        let err = parse(r#"{"deviceUuid": "1234"}"#);
+        assert_eq!(err.to_string(), "Missing field featureUuid error");
+        assert_eq!(err.kind(), "missing_field");
+
+        let err = parse(r#"{"deviceUuid": 1234}"#);
+        assert_eq!(
+            err.to_string(),
+            "Type mismatch error: invalid type: integer `1234`, expected a string at line 1 column 19"
+        );
+        assert_eq!(err.kind(), "type_mismatch");
+    }
 }
@@ -1,3 +1,13 @@
 pub mod amqp_error;
+pub mod config_error;
+pub mod decode_error;
+pub mod device_registry_error;
+pub mod limit_error;
 pub mod message_error;
 pub mod mqtt_error;
+pub mod rate_limit_error;
+pub mod registry_error;
+pub mod release_error;
+pub mod route_error;
+pub mod secret_error;
+pub mod topic_error;
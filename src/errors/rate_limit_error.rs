@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum RateLimitError {
+    #[error("cannot read rate limits file {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("cannot parse rate limits file {0}")]
+    Parse(String, #[source] serde_json::Error),
+    #[error("invalid rate limit of device class {0}: {1}")]
+    InvalidLimit(String, String),
+}
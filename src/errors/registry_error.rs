@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("cannot read sensor registry file {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("cannot parse sensor registry file {0}")]
+    Parse(String, #[source] serde_json::Error),
+    #[error("invalid feature {0} in sensor registry: {1}")]
+    InvalidFeature(String, String),
+}
@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum ReleaseError {
+    #[error("cannot parse release request")]
+    Parse(#[source] serde_json::Error),
+    #[error("release request of device {0} has an invalid signature")]
+    InvalidSignature(String),
+    #[error("release request of device {0} expired, signed {1} seconds from the receive time")]
+    Expired(String, i64),
+}
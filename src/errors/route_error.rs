@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum RouteError {
+    #[error("cannot read routes file {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("cannot parse routes file {0}")]
+    Parse(String, #[source] serde_json::Error),
+    #[error("invalid route {0}: {1}")]
+    InvalidRoute(String, String),
+}
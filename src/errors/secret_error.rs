@@ -0,0 +1,14 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("cannot read secret file {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("secret file {0} is writable by group or others (mode {1:o})")]
+    Permissions(String, u32),
+    #[error("secret file {0} is empty")]
+    Empty(String),
+    #[error("{0} or {0}_FILE is required")]
+    Missing(String),
+}
@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// custom error, based on 'thiserror' library
+#[derive(Error, Debug)]
+pub enum TopicError {
+    #[error("invalid topic template {0}: {1}")]
+    InvalidTemplate(String, String),
+    #[error("topic {0} doesn't match template {1}")]
+    Mismatch(String, String),
+    #[error("device id {0} is not a valid UUID")]
+    InvalidDeviceId(String),
+}
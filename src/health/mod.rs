@@ -0,0 +1,225 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use tracing::debug;
+
+// thresholds of liveness and readiness, 0 to disable the check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthPolicy {
+    // a broker disconnected for longer makes the producer not alive, so it's restarted
+    pub max_disconnected: TimeDelta,
+    // without a successful publish for longer the producer is not ready.
+    // It must be longer than the quietest period of the devices.
+    pub max_publish_age: TimeDelta,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        Self {
+            max_disconnected: TimeDelta::minutes(10),
+            max_publish_age: TimeDelta::zero(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Connectivity {
+    connected: bool,
+    // time of the last change
+    since: DateTime<Utc>,
+}
+
+impl Connectivity {
+    fn set(&mut self, connected: bool, now: DateTime<Utc>) {
+        if self.connected != connected {
+            self.connected = connected;
+            self.since = now;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HealthState {
+    amqp: Connectivity,
+    mqtt: Connectivity,
+    last_publish: Option<DateTime<Utc>>,
+}
+
+// result of a liveness or readiness check, returned as JSON by the HTTP listener
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthStatus {
+    pub ok: bool,
+    // why the check failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub amqp_connected: bool,
+    pub mqtt_connected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_publish: Option<DateTime<Utc>>,
+}
+
+impl HealthStatus {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+// connectivity of the brokers and time of the last successful publish,
+// updated by the AMQP and MQTT clients and checked by /healthz and /readyz.
+// Both brokers are disconnected until their clients connect. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub policy: HealthPolicy,
+    state: Arc<Mutex<HealthState>>,
+}
+
+impl Health {
+    pub fn new(policy: HealthPolicy, now: DateTime<Utc>) -> Self {
+        let disconnected = Connectivity {
+            connected: false,
+            since: now,
+        };
+        Self {
+            policy,
+            state: Arc::new(Mutex::new(HealthState {
+                amqp: disconnected,
+                mqtt: disconnected,
+                last_publish: None,
+            })),
+        }
+    }
+
+    pub fn set_amqp_connected(&self, connected: bool, now: DateTime<Utc>) {
+        self.state.lock().unwrap().amqp.set(connected, now);
+    }
+
+    pub fn set_mqtt_connected(&self, connected: bool, now: DateTime<Utc>) {
+        self.state.lock().unwrap().mqtt.set(connected, now);
+    }
+
+    pub fn record_publish(&self, now: DateTime<Utc>) {
+        self.state.lock().unwrap().last_publish = Some(now);
+    }
+
+    // fails if a broker is disconnected for longer than `max_disconnected`,
+    // for example while stuck in a retry loop
+    pub fn liveness(&self, now: DateTime<Utc>) -> HealthStatus {
+        let state = self.state.lock().unwrap();
+        let max = self.policy.max_disconnected;
+        let reason = [("AMQP", state.amqp), ("MQTT", state.mqtt)]
+            .into_iter()
+            .find(|(_, broker)| !broker.connected && max > TimeDelta::zero() && now - broker.since > max)
+            .map(|(name, broker)| {
+                format!(
+                    "{} disconnected for {} seconds",
+                    name,
+                    (now - broker.since).num_seconds()
+                )
+            });
+        status(&state, reason)
+    }
+
+    // fails if a broker is disconnected or, if enabled, without a successful publish for longer than
+    // `max_publish_age`. Before the first publish, the age is counted since both brokers are connected.
+    pub fn readiness(&self, now: DateTime<Utc>) -> HealthStatus {
+        let state = self.state.lock().unwrap();
+        let reason = if !state.amqp.connected {
+            Some(String::from("AMQP is not connected"))
+        } else if !state.mqtt.connected {
+            Some(String::from("MQTT is not connected"))
+        } else {
+            let max = self.policy.max_publish_age;
+            let last = state.last_publish.unwrap_or(state.amqp.since.max(state.mqtt.since));
+            (max > TimeDelta::zero() && now - last > max)
+                .then(|| format!("no successful publish for {} seconds", (now - last).num_seconds()))
+        };
+        if let Some(reason) = &reason {
+            debug!(target: "app", "readiness - producer is not ready, reason = {}", reason);
+        }
+        status(&state, reason)
+    }
+}
+
+fn status(state: &HealthState, reason: Option<String>) -> HealthStatus {
+    HealthStatus {
+        ok: reason.is_none(),
+        reason,
+        amqp_connected: state.amqp.connected,
+        mqtt_connected: state.mqtt.connected,
+        last_publish: state.last_publish,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::{Health, HealthPolicy};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    fn now() -> DateTime<Utc> {
+        "2025-12-25T10:30:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn check_liveness() {
+        let health = Health::new(HealthPolicy::default(), now());
+        assert!(health.liveness(now() + TimeDelta::minutes(10)).ok);
+        // stuck connecting to AMQP
+        let status = health.liveness(now() + TimeDelta::minutes(11));
+        assert_eq!(status.reason, Some(String::from("AMQP disconnected for 660 seconds")));
+        assert_eq!(
+            status.to_json(),
+            r#"{"ok":false,"reason":"AMQP disconnected for 660 seconds","amqpConnected":false,"mqttConnected":false}"#
+        );
+
+        health.set_amqp_connected(true, now() + TimeDelta::minutes(11));
+        health.set_mqtt_connected(true, now() + TimeDelta::minutes(11));
+        assert!(health.liveness(now() + TimeDelta::minutes(30)).ok);
+        // the disconnection is counted from the last change, shared by clones
+        health.clone().set_mqtt_connected(false, now() + TimeDelta::minutes(30));
+        health.set_mqtt_connected(false, now() + TimeDelta::minutes(35));
+        assert_eq!(
+            health.liveness(now() + TimeDelta::minutes(41)).reason,
+            Some(String::from("MQTT disconnected for 660 seconds"))
+        );
+    }
+
+    #[test]
+    fn check_readiness() {
+        let health = Health::new(
+            HealthPolicy {
+                max_publish_age: TimeDelta::minutes(5),
+                ..HealthPolicy::default()
+            },
+            now(),
+        );
+        assert_eq!(
+            health.readiness(now()).reason,
+            Some(String::from("AMQP is not connected"))
+        );
+        health.set_amqp_connected(true, now());
+        assert_eq!(
+            health.readiness(now()).reason,
+            Some(String::from("MQTT is not connected"))
+        );
+        health.set_mqtt_connected(true, now() + TimeDelta::minutes(1));
+        assert!(health.readiness(now() + TimeDelta::minutes(6)).ok);
+        assert_eq!(
+            health.readiness(now() + TimeDelta::minutes(7)).reason,
+            Some(String::from("no successful publish for 360 seconds"))
+        );
+
+        health.record_publish(now() + TimeDelta::minutes(7));
+        let status = health.readiness(now() + TimeDelta::minutes(8));
+        assert!(status.ok);
+        assert_eq!(status.last_publish, Some(now() + TimeDelta::minutes(7)));
+
+        // disabled check
+        let health = Health::new(HealthPolicy::default(), now());
+        health.set_amqp_connected(true, now());
+        health.set_mqtt_connected(true, now());
+        assert!(health.readiness(now() + TimeDelta::days(1)).ok);
+    }
+}
@@ -0,0 +1,111 @@
+use axum::Router;
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use chrono::Utc;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::health::{Health, HealthStatus};
+use crate::metrics::Metrics;
+
+// content type of the Prometheus text format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Clone)]
+struct HttpState {
+    metrics: Metrics,
+    health: Health,
+}
+
+// routes of the optional HTTP listener: Prometheus metrics, liveness and readiness
+pub fn router(metrics: Metrics, health: Health) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_liveness))
+        .route("/readyz", get(get_readiness))
+        .with_state(HttpState { metrics, health })
+}
+
+// serves the HTTP listener in a background task, without stopping the producer on errors
+pub async fn spawn_listener(addr: &str, metrics: Metrics, health: Health) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(target: "app", "spawn_listener - HTTP listener on {}", listener.local_addr()?);
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, router(metrics, health)).await {
+            error!(target: "app", "spawn_listener - HTTP listener stopped, err = {:?}", err);
+        }
+    });
+    Ok(())
+}
+
+async fn get_metrics(State(state): State<HttpState>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)], state.metrics.encode())
+}
+
+async fn get_liveness(State(state): State<HttpState>) -> impl IntoResponse {
+    health_response(state.health.liveness(Utc::now()))
+}
+
+async fn get_readiness(State(state): State<HttpState>) -> impl IntoResponse {
+    health_response(state.health.readiness(Utc::now()))
+}
+
+// 200 or 503, so probes fail without parsing the body
+fn health_response(status: HealthStatus) -> impl IntoResponse {
+    let code = if status.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, [(header::CONTENT_TYPE, "application/json")], status.to_json())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::{Health, HealthPolicy};
+    use crate::http::router;
+    use crate::metrics::Metrics;
+    use chrono::Utc;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn check_http_endpoints() {
+        let metrics = Metrics::default();
+        metrics.inc_received("temperature");
+        let health = Health::new(HealthPolicy::default(), Utc::now());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shared = health.clone();
+        tokio::spawn(async move { axum::serve(listener, router(metrics, shared)).await });
+
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("content-type: text/plain; version=0.0.4"));
+        assert!(response.contains("producer_mqtt_messages_received_total{feature=\"temperature\"} 1"));
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 200 OK"));
+        let response = get(addr, "/readyz").await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(
+            response.ends_with(
+                r#"{"ok":false,"reason":"AMQP is not connected","amqpConnected":false,"mqttConnected":false}"#
+            )
+        );
+        health.set_amqp_connected(true, Utc::now());
+        health.set_mqtt_connected(true, Utc::now());
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.1 200 OK"));
+    }
+}
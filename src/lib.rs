@@ -1,5 +1,9 @@
 pub mod amqp;
 pub mod config;
 pub mod errors;
+pub mod health;
+pub mod http;
+pub mod metrics;
 pub mod models;
 pub mod mqtt;
+pub mod redact;
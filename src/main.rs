@@ -1,35 +1,86 @@
 use std::time::Duration;
 
+use chrono::{TimeDelta, Utc};
 use futures::executor::block_on;
 use paho_mqtt::Message;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use producer::amqp::AmqpClient;
 use producer::config::{Env, init};
-use producer::errors::message_error::MessageError;
-use producer::mqtt::get_bytes_from_payload;
+use producer::errors::limit_error::LimitError;
+use producer::errors::message_error::{MessageError, RejectReason};
+use producer::health::{Health, HealthPolicy};
+use producer::http::spawn_listener;
+use producer::metrics::Metrics;
+use producer::models::message::Envelope;
+use producer::models::processing_config::ProcessingConfig;
+use producer::models::quarantine::ReleaseRequest;
+use producer::models::token_policy::API_TOKEN_HEADER;
 use producer::mqtt::mqtt_client::MqttClient;
 use producer::mqtt::mqtt_config::MqttConfig;
 use producer::mqtt::mqtt_options::MqttOptions;
-
-const TOPICS: &[&str] = &[
-    "sensors/+/temperature",
-    "sensors/+/humidity",
-    "sensors/+/light",
-    "sensors/+/motion",
-    "sensors/+/airquality",
-    "sensors/+/airpressure",
-    "sensors/+/online",
-];
+use producer::mqtt::{get_device_label, get_envelopes_from_payload, get_feature_label, get_rejected_bytes};
 
 #[tokio::main]
 async fn main() {
     // 1. Init logger and env
     let env: Env = init();
+    let processing_config: ProcessingConfig = match ProcessingConfig::new(&env) {
+        Ok(processing_config) => processing_config,
+        Err(err) => {
+            error!(target: "app", "Cannot create processing config, err = {:?}", err);
+            panic!("cannot create processing config");
+        }
+    };
+    let metrics = Metrics::default();
+    let health = Health::new(
+        HealthPolicy {
+            max_disconnected: TimeDelta::seconds(env.health_max_disconnected_secs),
+            max_publish_age: TimeDelta::seconds(env.ready_max_publish_age_secs),
+        },
+        Utc::now(),
+    );
+    if !env.http_listen_addr.is_empty()
+        && let Err(err) = spawn_listener(&env.http_listen_addr, metrics.clone(), health.clone()).await
+    {
+        error!(target: "app", "Cannot start HTTP listener on {}, err = {:?}", &env.http_listen_addr, err);
+        panic!("cannot start HTTP listener");
+    }
+    if let Some(devices) = processing_config.devices.clone()
+        && env.device_registry_reload_secs > 0
+    {
+        // reload the device registry when its file changes, without restarting the producer
+        let reload_period = Duration::from_secs(env.device_registry_reload_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(reload_period);
+            loop {
+                interval.tick().await;
+                if let Err(err) = devices.reload_if_modified() {
+                    error!(target: "app", "Cannot reload device registry, keeping the current one, err = {:?}", err);
+                }
+            }
+        });
+    }
 
     // 2. Init RabbitMQ
     info!(target: "app", "Initializing RabbitMQ...");
     let mut amqp_client = AmqpClient::new(env.amqp_uri.clone(), env.amqp_queue_name.clone());
+    amqp_client.set_uri_file(env.amqp_uri_file.clone());
+    // metrics of publishes need publisher confirms, so they are enabled only if they can be scraped
+    if !env.http_listen_addr.is_empty() {
+        amqp_client.set_metrics(metrics.clone());
+    }
+    amqp_client.set_health_check(health.clone());
+    amqp_client.set_content_type(processing_config.output_codec.content_type().to_string());
+    if let Some(rejects_queue_name) = &processing_config.rejects_queue_name {
+        amqp_client.add_queue(rejects_queue_name.clone());
+    }
+    if let Some(management_queue_name) = &processing_config.management_queue_name {
+        amqp_client.add_queue(management_queue_name.clone());
+    }
+    for route in &processing_config.routes {
+        amqp_client.add_queue(route.queue.clone());
+    }
     amqp_client.connect_with_retry_loop().await;
 
     // 3. Init MQTT
@@ -37,15 +88,28 @@ async fn main() {
     let mqtt_config: MqttConfig = MqttConfig::new(&env);
     match MqttClient::new(MqttOptions::new(&mqtt_config)) {
         Ok(mut mqtt_client) => {
+            mqtt_client.set_reload_config(mqtt_config);
+            mqtt_client.set_metrics(metrics.clone());
+            mqtt_client.set_health_check(health);
             mqtt_client.connect().await;
-            if let Err(err) = mqtt_client.subscribe(TOPICS).await {
-                error!(target: "app", "MQTT cannot subscribe to TOPICS, err = {:?}", err);
-                panic!("unknown error, because MQTT cannot subscribe to TOPICS");
+            if let Err(err) = mqtt_client.subscribe(&processing_config.topics()).await {
+                error!(target: "app", "MQTT cannot subscribe to topics, err = {:?}", err);
+                panic!("unknown error, because MQTT cannot subscribe to topics");
             }
             // 4. Wait for incoming MQTT messages
             info!(target: "app", "Waiting for incoming MQTT messages");
+            // outgoing messages are encoded in the same buffer, to avoid an allocation for each of them
+            let mut msg_buffer: Vec<u8> = Vec::with_capacity(1024);
             while let Some(msg_opt) = mqtt_client.get_next_message().await {
-                let _ = process_mqtt_message(&msg_opt, &mut mqtt_client, &mut amqp_client).await;
+                let _ = process_mqtt_message(
+                    &msg_opt,
+                    &mut msg_buffer,
+                    &mut mqtt_client,
+                    &mut amqp_client,
+                    &processing_config,
+                    &metrics,
+                )
+                .await;
             }
         }
         Err(err) => {
@@ -57,38 +121,51 @@ async fn main() {
 
 async fn process_mqtt_message(
     msg_opt: &Option<Message>,
+    msg_buffer: &mut Vec<u8>,
     mqtt_client: &mut MqttClient,
     amqp_client: &mut AmqpClient,
+    processing_config: &ProcessingConfig,
+    metrics: &Metrics,
 ) -> Result<(), anyhow::Error> {
     if let Some(msg) = msg_opt {
         debug!(target: "app", "listen_for_messages - MQTT message received");
-        let msg_byte: Vec<u8> = get_bytes_from_payload(msg);
-        // return this if
-        if msg_byte.is_empty() {
-            // msg is not valid, because empty
-            debug!(target: "app", "listen_for_messages - Empty msg_byte received");
-            Err(anyhow::Error::from(MessageError::EmptyMessageError))
-        } else {
-            // return the result of block_on(...)
-            block_on(async {
-                if !amqp_client.is_connected() {
-                    error!(target: "app", "listen_for_messages - AMQP channel is not connected, reconnecting...");
-                    amqp_client.connect_with_retry_loop().await;
-                }
-                debug!(target: "app", "listen_for_messages - Publishing message via AMQP...");
-                // send via AMQP
-                match amqp_client.publish_message(msg_byte).await {
-                    Ok(_) => {
-                        debug!(target: "app", "listen_for_messages - AMQP message published to queue {}", amqp_client.amqp_queue_name);
-                        Ok(())
-                    }
-                    Err(err) => {
-                        error!(target: "app", "listen_for_messages - Cannot publish AMQP message to queue {}. Err ={:?}", amqp_client.amqp_queue_name, err);
-                        Err(anyhow::Error::from(MessageError::PublishMessageError))
+        if Some(msg.topic()) == processing_config.quarantine_release_topic.as_deref() {
+            let now = Utc::now();
+            match ReleaseRequest::verify(
+                msg.payload(),
+                &processing_config.quarantine_release_key,
+                processing_config.signature_policy.max_age,
+                now,
+            ) {
+                Ok(request) => {
+                    if !processing_config.quarantine.release(&request.device_id, now) {
+                        info!(target: "app", "listen_for_messages - device {} is not quarantined", &request.device_id);
                     }
                 }
-            })
+                Err(err) => {
+                    warn!(target: "app", "listen_for_messages - release request rejected, err = {}", err);
+                    return Ok(());
+                }
+            }
+            return publish_quarantine_events(amqp_client, processing_config);
+        }
+        metrics.inc_received(&get_feature_label(msg, processing_config));
+        // a single MQTT message can contain many readings, publish each of them
+        let mut result = Ok(());
+        for envelope_result in get_envelopes_from_payload(msg, processing_config) {
+            if let Err(err) = process_reading(
+                msg,
+                envelope_result,
+                msg_buffer,
+                amqp_client,
+                processing_config,
+                metrics,
+            ) {
+                result = Err(err);
+            }
         }
+        publish_quarantine_events(amqp_client, processing_config)?;
+        result
     } else {
         // msg_opt="None" means we were disconnected. Try to reconnect...
         error!(target: "app", "listen_for_messages - Lost connection. Attempting reconnect in 5 seconds...");
@@ -101,6 +178,160 @@ async fn process_mqtt_message(
     }
 }
 
+fn process_reading(
+    msg: &Message,
+    envelope_result: Result<Envelope, MessageError>,
+    msg_buffer: &mut Vec<u8>,
+    amqp_client: &mut AmqpClient,
+    processing_config: &ProcessingConfig,
+    metrics: &Metrics,
+) -> Result<(), anyhow::Error> {
+    match envelope_result {
+        Ok(mut envelope) => {
+            if envelope.device_mismatch() {
+                let device_id = envelope.topic().device_id.as_str();
+                let label = get_device_label(device_id, processing_config, metrics);
+                let count = metrics.inc_device_mismatch(&label);
+                debug!(target: "app", "process_reading - device mismatch on topic of device {} ({} times for label {})", device_id, count, label);
+            }
+            metrics.inc_signature(if envelope.signature_verified() {
+                "valid"
+            } else {
+                "unsigned"
+            });
+            let api_token = envelope.take_api_token();
+            let queue_name = amqp_client.amqp_queue_name.clone();
+            let codec = processing_config.output_codec;
+            let header = envelope.forward_api_token(
+                &api_token,
+                processing_config.api_token_policy,
+                &processing_config.api_token_hash_key,
+            );
+            envelope.encode_into(codec, processing_config.schema_version, msg_buffer);
+            block_on(publish_via_amqp(
+                amqp_client,
+                &queue_name,
+                msg_buffer,
+                codec.content_type(),
+                header.as_deref(),
+            ))?;
+            metrics.observe_published(envelope.received_at(), envelope.timestamp(), Utc::now());
+            // publish also to additional routes, each one with its schema version and apiToken policy
+            let feature_name = envelope.topic().feature_name.clone();
+            for route in processing_config
+                .routes
+                .iter()
+                .filter(|route| route.matches(&feature_name))
+            {
+                let policy = route.api_token_policy.unwrap_or(processing_config.api_token_policy);
+                let header = envelope.forward_api_token(&api_token, policy, &processing_config.api_token_hash_key);
+                envelope.encode_into(codec, route.schema_version, msg_buffer);
+                block_on(publish_via_amqp(
+                    amqp_client,
+                    &route.queue,
+                    msg_buffer,
+                    codec.content_type(),
+                    header.as_deref(),
+                ))?;
+            }
+            Ok(())
+        }
+        Err(err) => {
+            match &err {
+                MessageError::Rejected { feature, reason } => {
+                    if let RejectReason::DeviceMismatch(device_id, _) = reason {
+                        metrics.inc_device_mismatch(&get_device_label(device_id, processing_config, metrics));
+                    }
+                    if reason.is_signature() {
+                        metrics.inc_signature(reason.kind());
+                    }
+                    let count = metrics.inc_rejected(feature, reason);
+                    debug!(target: "app", "process_reading - {} reading rejected ({} times for reason {})", feature, count, reason.kind());
+                }
+                MessageError::LimitExceeded(limit) => {
+                    // a separate class, because these messages are not parsed at all
+                    let count = metrics.inc_limit(limit);
+                    debug!(target: "app", "process_reading - message exceeds payload limits ({} times for kind {}), err = {}", count, limit.kind(), limit);
+                }
+                _ => {
+                    // msg is not valid
+                    let count = metrics.inc_invalid(&err);
+                    debug!(target: "app", "process_reading - Invalid message received ({} times for kind {}), err = {:?}", count, err.kind(), err);
+                }
+            }
+            // messages dropped by rate limits would flood also the rejects queue,
+            // and oversize payloads would be copied there
+            let skip_rejects = matches!(&err, MessageError::Rejected { reason, .. } if reason.is_flood())
+                || matches!(&err, MessageError::LimitExceeded(LimitError::PayloadTooLarge(..)));
+            if let Some(rejects_queue_name) = &processing_config.rejects_queue_name
+                && !skip_rejects
+            {
+                let rejected_byte = get_rejected_bytes(msg, &err, processing_config);
+                // rejected envelopes are always JSON
+                block_on(publish_via_amqp(
+                    amqp_client,
+                    rejects_queue_name,
+                    &rejected_byte,
+                    "application/json",
+                    None,
+                ))?;
+            }
+            Err(anyhow::Error::from(err))
+        }
+    }
+}
+
+// devices entering or leaving the quarantine, published to the management queue if defined
+fn publish_quarantine_events(
+    amqp_client: &mut AmqpClient,
+    processing_config: &ProcessingConfig,
+) -> Result<(), anyhow::Error> {
+    for event in processing_config.quarantine.take_events() {
+        let event_json = event.to_json();
+        info!(target: "app", "publish_quarantine_events - quarantine event = {}", &event_json);
+        if let Some(management_queue_name) = &processing_config.management_queue_name {
+            block_on(publish_via_amqp(
+                amqp_client,
+                management_queue_name,
+                event_json.as_bytes(),
+                "application/json",
+                None,
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+async fn publish_via_amqp(
+    amqp_client: &mut AmqpClient,
+    queue_name: &str,
+    msg_byte: &[u8],
+    content_type: &str,
+    api_token_header: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    if !amqp_client.is_connected() {
+        error!(target: "app", "publish_via_amqp - AMQP channel is not connected, reconnecting...");
+        amqp_client.connect_with_retry_loop().await;
+    }
+    debug!(target: "app", "publish_via_amqp - Publishing message via AMQP...");
+    // send via AMQP
+    let header = api_token_header.map(|api_token| (API_TOKEN_HEADER, api_token));
+    match amqp_client
+        .publish_message_with_header(queue_name, msg_byte, content_type, header)
+        .await
+    {
+        Ok(confirm) => {
+            debug!(target: "app", "publish_via_amqp - AMQP message published to queue {}", queue_name);
+            amqp_client.track_confirm(confirm);
+            Ok(())
+        }
+        Err(err) => {
+            error!(target: "app", "publish_via_amqp - Cannot publish AMQP message to queue {}. Err ={:?}", queue_name, err);
+            Err(anyhow::Error::from(MessageError::PublishMessageError))
+        }
+    }
+}
+
 // testing
 #[cfg(test)]
 mod tests_integration;
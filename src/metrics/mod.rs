@@ -0,0 +1,406 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+use crate::errors::limit_error::LimitError;
+use crate::errors::message_error::{MessageError, RejectReason};
+
+// devices counted one by one by `producer_readings_device_mismatches_total`, the others are `other`
+pub const MAX_DEVICE_LABELS: usize = 100;
+pub const OTHER_DEVICE_LABEL: &str = "other";
+
+// Prometheus metrics, served by the optional HTTP listener on /metrics.
+// Names follow the scheme `producer_<area>_<what>[_<unit>]`:
+// - area is `mqtt` for incoming messages, `readings` for validation of readings,
+//   `amqp` for publishing and `pipeline` for the whole processing
+// - counters end with `_total`, histograms of durations with `_seconds`, gauges have no suffix
+// - label names and values are snake_case, reasons and kinds are the labels of `kind()`
+// - values of labels come from a bounded set, never from payloads or topics as they are:
+//   `feature` is a feature of the registry or `unknown` (see `get_feature_label`) and
+//   `device_id` is one of the first MAX_DEVICE_LABELS devices with a mismatch or `other`,
+//   and only devices of the device registry, if any, are counted one by one (see `get_device_label`)
+//
+// | name                                          | type      | labels          |
+// |-----------------------------------------------|-----------|-----------------|
+// | producer_mqtt_messages_received_total         | counter   | feature         |
+// | producer_mqtt_messages_invalid_total          | counter   | kind            |
+// | producer_mqtt_payload_limits_exceeded_total   | counter   | kind            |
+// | producer_mqtt_reconnects_total                | counter   |                 |
+// | producer_mqtt_stream_buffered_messages        | gauge     |                 |
+// | producer_readings_rejected_total              | counter   | feature, reason |
+// | producer_readings_device_mismatches_total     | counter   | device_id       |
+// | producer_readings_signatures_total            | counter   | outcome         |
+// | producer_amqp_publishes_total                 | counter   | queue, result   |
+// | producer_amqp_confirms_total                  | counter   | result          |
+// | producer_amqp_reconnects_total                | counter   |                 |
+// | producer_pipeline_processing_seconds          | histogram |                 |
+// | producer_pipeline_end_to_end_seconds          | histogram |                 |
+//
+// Clones share the same metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    // MQTT messages by feature of the topic, `unknown` for features not in the registry
+    received: IntCounterVec,
+    // invalid messages by error kind
+    invalid: IntCounterVec,
+    // messages exceeding payload limits, by limit kind
+    limits: IntCounterVec,
+    mqtt_reconnects: IntCounter,
+    // messages received from the broker and not processed yet
+    mqtt_buffered: IntGauge,
+    // rejected readings by feature and reason kind
+    rejected: IntCounterVec,
+    // readings with a deviceUuid different from the topic, by device id of the topic or `other`
+    device_mismatches: IntCounterVec,
+    // values of the `device_id` label of device mismatches, except `other`
+    device_labels: Arc<Mutex<HashSet<String>>>,
+    // outcomes of signature verifications: valid, unsigned or the reject reason kind
+    signatures: IntCounterVec,
+    // publishes by queue and result: ok or error
+    publishes: IntCounterVec,
+    // confirmations of the broker: ack or nack
+    confirms: IntCounterVec,
+    amqp_reconnects: IntCounter,
+    // from the reception of the MQTT message to the publish of the reading
+    processing: Histogram,
+    // from the timestamp of the reading to its publish, only for readings with a timestamp
+    end_to_end: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new();
+        let counter_vec = |name: &str, help: &str, labels: &[&str]| {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let counter = |name: &str, help: &str| {
+            let counter = IntCounter::new(name, help).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let histogram = |name: &str, help: &str, buckets: Vec<f64>| {
+            let histogram = Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets)).unwrap();
+            registry.register(Box::new(histogram.clone())).unwrap();
+            histogram
+        };
+        let mqtt_buffered = IntGauge::new(
+            "producer_mqtt_stream_buffered_messages",
+            "MQTT messages received and not processed yet",
+        )
+        .unwrap();
+        registry.register(Box::new(mqtt_buffered.clone())).unwrap();
+        Self {
+            received: counter_vec(
+                "producer_mqtt_messages_received_total",
+                "MQTT messages received, by feature",
+                &["feature"],
+            ),
+            invalid: counter_vec(
+                "producer_mqtt_messages_invalid_total",
+                "MQTT messages that cannot be parsed, by error kind",
+                &["kind"],
+            ),
+            limits: counter_vec(
+                "producer_mqtt_payload_limits_exceeded_total",
+                "MQTT messages exceeding payload limits, by limit kind",
+                &["kind"],
+            ),
+            mqtt_reconnects: counter("producer_mqtt_reconnects_total", "reconnections to the MQTT broker"),
+            mqtt_buffered,
+            rejected: counter_vec(
+                "producer_readings_rejected_total",
+                "readings rejected by validation, by feature and reason",
+                &["feature", "reason"],
+            ),
+            device_mismatches: counter_vec(
+                "producer_readings_device_mismatches_total",
+                "readings with a deviceUuid different from the topic, by device id of the topic",
+                &["device_id"],
+            ),
+            device_labels: Arc::new(Mutex::new(HashSet::new())),
+            signatures: counter_vec(
+                "producer_readings_signatures_total",
+                "signature verifications, by outcome",
+                &["outcome"],
+            ),
+            publishes: counter_vec(
+                "producer_amqp_publishes_total",
+                "AMQP publishes, by queue and result",
+                &["queue", "result"],
+            ),
+            confirms: counter_vec(
+                "producer_amqp_confirms_total",
+                "AMQP publisher confirms, by result",
+                &["result"],
+            ),
+            amqp_reconnects: counter("producer_amqp_reconnects_total", "reconnections to the AMQP broker"),
+            processing: histogram(
+                "producer_pipeline_processing_seconds",
+                "time from the reception of the MQTT message to the publish of the reading",
+                vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0],
+            ),
+            end_to_end: histogram(
+                "producer_pipeline_end_to_end_seconds",
+                "time from the timestamp of the reading to its publish",
+                vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0],
+            ),
+            registry,
+        }
+    }
+}
+
+impl Metrics {
+    // increments the received messages counter and returns the new value
+    pub fn inc_received(&self, feature: &str) -> u64 {
+        inc(&self.received, &[feature])
+    }
+
+    // increments the rejected readings counter and returns the new value
+    pub fn inc_rejected(&self, feature: &str, reason: &RejectReason) -> u64 {
+        inc(&self.rejected, &[feature, reason.kind()])
+    }
+
+    // increments the invalid messages counter and returns the new value
+    pub fn inc_invalid(&self, err: &MessageError) -> u64 {
+        inc(&self.invalid, &[err.kind()])
+    }
+
+    // increments the payload limits counter and returns the new value
+    pub fn inc_limit(&self, err: &LimitError) -> u64 {
+        inc(&self.limits, &[err.kind()])
+    }
+
+    // label of the device in the device mismatches counter: the device id itself,
+    // until MAX_DEVICE_LABELS devices have a label, and `other` for the next ones
+    pub fn device_label(&self, device_id: &str) -> String {
+        let mut labels = self.device_labels.lock().unwrap();
+        if labels.contains(device_id) {
+            return device_id.to_string();
+        }
+        if device_id == OTHER_DEVICE_LABEL || labels.len() >= MAX_DEVICE_LABELS {
+            return String::from(OTHER_DEVICE_LABEL);
+        }
+        labels.insert(device_id.to_string());
+        device_id.to_string()
+    }
+
+    // increments the device mismatches counter of a label of `device_label` and returns the new value
+    pub fn inc_device_mismatch(&self, device_id: &str) -> u64 {
+        inc(&self.device_mismatches, &[device_id])
+    }
+
+    // increments the signature verifications counter and returns the new value
+    pub fn inc_signature(&self, outcome: &'static str) -> u64 {
+        inc(&self.signatures, &[outcome])
+    }
+
+    // increments the publishes counter and returns the new value
+    pub fn inc_publish(&self, queue: &str, ok: bool) -> u64 {
+        inc(&self.publishes, &[queue, if ok { "ok" } else { "error" }])
+    }
+
+    // increments the publisher confirms counter and returns the new value
+    pub fn inc_confirm(&self, ack: bool) -> u64 {
+        inc(&self.confirms, &[if ack { "ack" } else { "nack" }])
+    }
+
+    pub fn inc_mqtt_reconnect(&self) {
+        self.mqtt_reconnects.inc();
+    }
+
+    pub fn inc_amqp_reconnect(&self) {
+        self.amqp_reconnects.inc();
+    }
+
+    pub fn set_mqtt_buffered(&self, messages: usize) {
+        self.mqtt_buffered.set(messages as i64);
+    }
+
+    // records the latencies of a published reading.
+    // Timestamps in the future, because of clock skew, count as 0.
+    pub fn observe_published(&self, received_at: DateTime<Utc>, timestamp: Option<DateTime<Utc>>, now: DateTime<Utc>) {
+        self.processing.observe(seconds(now - received_at));
+        if let Some(timestamp) = timestamp {
+            self.end_to_end.observe(seconds(now - timestamp));
+        }
+    }
+
+    // all metrics in the Prometheus text format
+    pub fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .unwrap_or_default()
+    }
+
+    pub fn received(&self, feature: &str) -> u64 {
+        self.received.with_label_values(&[feature]).get()
+    }
+
+    pub fn publishes(&self, queue: &str, result: &str) -> u64 {
+        self.publishes.with_label_values(&[queue, result]).get()
+    }
+
+    pub fn signatures(&self, outcome: &str) -> u64 {
+        self.signatures.with_label_values(&[outcome]).get()
+    }
+
+    pub fn device_mismatches(&self, device_id: &str) -> u64 {
+        self.device_mismatches.with_label_values(&[device_id]).get()
+    }
+
+    pub fn limits(&self, kind: &str) -> u64 {
+        self.limits.with_label_values(&[kind]).get()
+    }
+
+    pub fn invalid(&self, kind: &str) -> u64 {
+        self.invalid.with_label_values(&[kind]).get()
+    }
+
+    pub fn rejected(&self, feature: &str, reason_kind: &str) -> u64 {
+        self.rejected.with_label_values(&[feature, reason_kind]).get()
+    }
+}
+
+fn inc(counter: &IntCounterVec, labels: &[&str]) -> u64 {
+    let counter = counter.with_label_values(labels);
+    counter.inc();
+    counter.get()
+}
+
+fn seconds(duration: chrono::TimeDelta) -> f64 {
+    duration
+        .num_microseconds()
+        .map_or(0.0, |micros| micros.max(0) as f64 / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::limit_error::LimitError;
+    use crate::errors::message_error::{MessageError, RejectReason};
+    use crate::metrics::{MAX_DEVICE_LABELS, Metrics, OTHER_DEVICE_LABEL};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_rejected_counters() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.inc_rejected("temperature", &RejectReason::NotFinite), 1);
+        assert_eq!(
+            metrics.inc_rejected("temperature", &RejectReason::AboveMax(300.0, 100.0)),
+            1
+        );
+        assert_eq!(
+            metrics.inc_rejected("temperature", &RejectReason::AboveMax(400.0, 100.0)),
+            2
+        );
+        assert_eq!(metrics.rejected("temperature", "above_max"), 2);
+        assert_eq!(metrics.rejected("humidity", "above_max"), 0);
+    }
+
+    #[test]
+    fn check_invalid_counters() {
+        let metrics = Metrics::default();
+        assert_eq!(
+            metrics.inc_invalid(&MessageError::UnknownFeature(String::from("foo"))),
+            1
+        );
+        assert_eq!(
+            metrics.inc_invalid(&MessageError::UnknownFeature(String::from("bar"))),
+            2
+        );
+        assert_eq!(
+            metrics.inc_invalid(&MessageError::MissingField(String::from("apiToken"))),
+            1
+        );
+        assert_eq!(metrics.invalid("unknown_feature"), 2);
+        assert_eq!(metrics.invalid("invalid_json"), 0);
+    }
+
+    #[test]
+    fn check_device_mismatch_counters() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.inc_device_mismatch("device-1"), 1);
+        assert_eq!(metrics.inc_device_mismatch("device-1"), 2);
+        assert_eq!(metrics.inc_device_mismatch("device-2"), 1);
+        assert_eq!(metrics.device_mismatches("device-1"), 2);
+        assert_eq!(metrics.device_mismatches("device-3"), 0);
+
+        // labels are capped, shared by clones
+        for i in 0..MAX_DEVICE_LABELS {
+            assert_eq!(
+                metrics.clone().device_label(&format!("device-{}", i)),
+                format!("device-{}", i)
+            );
+        }
+        assert_eq!(metrics.device_label("device-1"), "device-1");
+        assert_eq!(metrics.device_label("device-new"), OTHER_DEVICE_LABEL);
+    }
+
+    #[test]
+    fn check_limit_counters() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.inc_limit(&LimitError::PayloadTooLarge(100, 10)), 1);
+        assert_eq!(metrics.inc_limit(&LimitError::PayloadTooLarge(200, 10)), 2);
+        assert_eq!(metrics.inc_limit(&LimitError::TooDeep(16)), 1);
+        assert_eq!(metrics.limits("payload_too_large"), 2);
+        assert_eq!(metrics.limits("unknown_field"), 0);
+        // not counted as invalid messages
+        assert_eq!(metrics.invalid("limit_exceeded"), 0);
+    }
+
+    #[test]
+    fn check_signature_counters() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.inc_signature("valid"), 1);
+        assert_eq!(metrics.inc_signature("valid"), 2);
+        assert_eq!(
+            metrics.inc_signature(RejectReason::ReplayedNonce(String::from("n")).kind()),
+            1
+        );
+        assert_eq!(metrics.signatures("valid"), 2);
+        assert_eq!(metrics.signatures("replayed_nonce"), 1);
+        assert_eq!(metrics.signatures("unsigned"), 0);
+    }
+
+    #[test]
+    fn check_encode_metrics() {
+        let metrics = Metrics::default();
+        let shared = metrics.clone();
+        assert_eq!(metrics.inc_received("temperature"), 1);
+        assert_eq!(metrics.inc_publish("ks89", true), 1);
+        assert_eq!(metrics.inc_publish("ks89", false), 1);
+        metrics.inc_confirm(false);
+        metrics.inc_amqp_reconnect();
+        metrics.set_mqtt_buffered(3);
+        let received_at: DateTime<Utc> = "2025-12-25T10:30:00Z".parse().unwrap();
+        metrics.observe_published(
+            received_at,
+            Some(received_at - TimeDelta::seconds(2)),
+            received_at + TimeDelta::milliseconds(3),
+        );
+        // a timestamp in the future counts as 0
+        metrics.observe_published(received_at, Some(received_at + TimeDelta::seconds(10)), received_at);
+        assert_eq!(shared.received("temperature"), 1);
+        assert_eq!(shared.publishes("ks89", "ok"), 1);
+
+        let text = shared.encode();
+        for line in [
+            "producer_mqtt_messages_received_total{feature=\"temperature\"} 1",
+            "producer_amqp_publishes_total{queue=\"ks89\",result=\"error\"} 1",
+            "producer_amqp_confirms_total{result=\"nack\"} 1",
+            "producer_amqp_reconnects_total 1",
+            "producer_mqtt_reconnects_total 0",
+            "producer_mqtt_stream_buffered_messages 3",
+            "producer_pipeline_processing_seconds_bucket{le=\"0.005\"} 2",
+            "producer_pipeline_end_to_end_seconds_bucket{le=\"0.01\"} 1",
+            "producer_pipeline_end_to_end_seconds_bucket{le=\"2.5\"} 2",
+        ] {
+            assert!(text.contains(line), "missing {}", line);
+        }
+    }
+}
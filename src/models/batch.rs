@@ -0,0 +1,84 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::message_error::RejectReason;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchMode {
+    // publish a message for each value, ordered by timestamp
+    #[default]
+    Expand,
+    // publish a single message with all values of the batch
+    Envelope,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchPolicy {
+    pub mode: BatchMode,
+    // maximum number of values in a batch, bigger batches are rejected as a whole
+    pub max_size: usize,
+    // maximum age of a value, compared to the time of reception
+    pub max_age: TimeDelta,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        Self {
+            mode: BatchMode::Expand,
+            max_size: 100,
+            max_age: TimeDelta::days(7),
+        }
+    }
+}
+
+impl BatchPolicy {
+    pub fn check_size(&self, size: usize) -> Result<(), RejectReason> {
+        if size > self.max_size {
+            return Err(RejectReason::BatchTooLarge(size, self.max_size));
+        }
+        Ok(())
+    }
+
+    pub fn check_age(&self, timestamp: DateTime<Utc>, received_at: DateTime<Utc>) -> Result<(), RejectReason> {
+        let age = received_at - timestamp;
+        if age > self.max_age {
+            return Err(RejectReason::TooOld(age.num_seconds()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::RejectReason;
+    use crate::models::batch::BatchPolicy;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_batch_policy() {
+        let received_at: DateTime<Utc> = "2025-12-25T10:30:00Z".parse().unwrap();
+        let policy = BatchPolicy {
+            max_size: 2,
+            max_age: TimeDelta::hours(1),
+            ..BatchPolicy::default()
+        };
+
+        assert_eq!(policy.check_size(2), Ok(()));
+        assert_eq!(policy.check_size(3), Err(RejectReason::BatchTooLarge(3, 2)));
+
+        assert_eq!(
+            policy.check_age(received_at - TimeDelta::minutes(59), received_at),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check_age(received_at + TimeDelta::minutes(5), received_at),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check_age(received_at - TimeDelta::hours(2), received_at),
+            Err(RejectReason::TooOld(7200))
+        );
+    }
+}
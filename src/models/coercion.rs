@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::payload_trait::FeatureValue;
+use crate::models::sensor_registry::ValueType;
+
+// how strictly payload values must match the type of their feature.
+// Mappings of the sensor registry, like "ON" -> 1, are applied in both modes.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CoercionMode {
+    // only ints are accepted for float features
+    #[default]
+    Strict,
+    // accept also numeric strings, integral floats for int features and 0/1 for bool features
+    Lenient,
+}
+
+impl CoercionMode {
+    // returns the value converted to `value_type` if allowed by this mode,
+    // otherwise the value as is, so validation can report the type mismatch
+    pub fn coerce(&self, value: FeatureValue, value_type: ValueType) -> FeatureValue {
+        if *self == CoercionMode::Strict {
+            return value;
+        }
+        match (value_type, value) {
+            (ValueType::Float, FeatureValue::String(text)) => match text.trim().parse::<f64>() {
+                Ok(v) => FeatureValue::Float(v),
+                Err(_) => FeatureValue::String(text),
+            },
+            (ValueType::Int, FeatureValue::Float(v)) if is_integral(v) => FeatureValue::Int(v as i64),
+            (ValueType::Int, FeatureValue::String(text)) => match text.trim().parse::<f64>() {
+                Ok(v) if is_integral(v) => FeatureValue::Int(v as i64),
+                _ => FeatureValue::String(text),
+            },
+            (ValueType::Bool, FeatureValue::Int(v)) if v == 0 || v == 1 => FeatureValue::Bool(v == 1),
+            (ValueType::Bool, FeatureValue::String(text)) => match text.trim() {
+                "0" | "false" => FeatureValue::Bool(false),
+                "1" | "true" => FeatureValue::Bool(true),
+                _ => FeatureValue::String(text),
+            },
+            (_, value) => value,
+        }
+    }
+}
+
+fn is_integral(value: f64) -> bool {
+    value.is_finite() && value.fract() == 0.0 && value.abs() < i64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::coercion::CoercionMode;
+    use crate::models::payload_trait::FeatureValue;
+    use crate::models::sensor_registry::ValueType;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_coerce() {
+        let text = |v: &str| FeatureValue::String(v.to_string());
+
+        // strict mode never changes the value
+        assert_eq!(
+            CoercionMode::Strict.coerce(text("21.5"), ValueType::Float),
+            text("21.5")
+        );
+        assert_eq!(
+            CoercionMode::Strict.coerce(FeatureValue::Float(5.0), ValueType::Int),
+            FeatureValue::Float(5.0)
+        );
+
+        let lenient = CoercionMode::Lenient;
+        assert_eq!(
+            lenient.coerce(text(" 21.5 "), ValueType::Float),
+            FeatureValue::Float(21.5)
+        );
+        assert_eq!(lenient.coerce(text("warm"), ValueType::Float), text("warm"));
+        assert_eq!(
+            lenient.coerce(FeatureValue::Float(5.0), ValueType::Int),
+            FeatureValue::Int(5)
+        );
+        assert_eq!(
+            lenient.coerce(FeatureValue::Float(5.5), ValueType::Int),
+            FeatureValue::Float(5.5)
+        );
+        assert_eq!(lenient.coerce(text("1"), ValueType::Int), FeatureValue::Int(1));
+        assert_eq!(
+            lenient.coerce(FeatureValue::Int(1), ValueType::Bool),
+            FeatureValue::Bool(true)
+        );
+        assert_eq!(
+            lenient.coerce(text("false"), ValueType::Bool),
+            FeatureValue::Bool(false)
+        );
+        assert_eq!(
+            lenient.coerce(FeatureValue::Int(2), ValueType::Bool),
+            FeatureValue::Int(2)
+        );
+        assert_eq!(lenient.coerce(text("open"), ValueType::Enum), text("open"));
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::errors::message_error::RejectReason;
+
+// what to do when the deviceUuid of the payload doesn't match the device id of the MQTT topic,
+// for example because a device publishes on the topic of another device
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceMismatchAction {
+    // drop the reading
+    Reject,
+    // replace the deviceUuid with the device id of the topic, marking the message with deviceMismatch
+    Overwrite,
+    // forward the deviceUuid of the payload, marking the message with deviceMismatch
+    #[default]
+    Flag,
+}
+
+impl DeviceMismatchAction {
+    // returns the device uuid to forward and if it didn't match the topic,
+    // or the reason why the reading must be rejected
+    pub fn apply<'a>(&self, topic_device_id: &'a str, device_uuid: &'a str) -> Result<(&'a str, bool), RejectReason> {
+        // UUIDs are case-insensitive
+        if topic_device_id.eq_ignore_ascii_case(device_uuid) {
+            return Ok((device_uuid, false));
+        }
+        warn!(target: "app", "apply - deviceUuid {} doesn't match device id {} of the topic", device_uuid, topic_device_id);
+        match self {
+            DeviceMismatchAction::Reject => Err(RejectReason::DeviceMismatch(
+                topic_device_id.to_string(),
+                device_uuid.to_string(),
+            )),
+            DeviceMismatchAction::Overwrite => Ok((topic_device_id, true)),
+            DeviceMismatchAction::Flag => Ok((device_uuid, true)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::RejectReason;
+    use crate::models::device_check::DeviceMismatchAction;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_device_mismatch_action() {
+        let topic_device_id = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let other = "0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60";
+
+        for action in [
+            DeviceMismatchAction::Reject,
+            DeviceMismatchAction::Overwrite,
+            DeviceMismatchAction::Flag,
+        ] {
+            assert_eq!(
+                action.apply(topic_device_id, "246E3256-F0DD-4FCB-82C5-EE20C2267EEB"),
+                Ok(("246E3256-F0DD-4FCB-82C5-EE20C2267EEB", false))
+            );
+        }
+        assert_eq!(
+            DeviceMismatchAction::Reject.apply(topic_device_id, other),
+            Err(RejectReason::DeviceMismatch(
+                topic_device_id.to_string(),
+                other.to_string()
+            ))
+        );
+        assert_eq!(
+            DeviceMismatchAction::Overwrite.apply(topic_device_id, other),
+            Ok((topic_device_id, true))
+        );
+        assert_eq!(
+            DeviceMismatchAction::Flag.apply(topic_device_id, other),
+            Ok((other, true))
+        );
+    }
+}
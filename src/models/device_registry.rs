@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::fs::{metadata, read_to_string};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tracing::{debug, info};
+
+use crate::errors::device_registry_error::DeviceRegistryError;
+use crate::errors::message_error::RejectReason;
+use crate::models::rate_limit::DEFAULT_DEVICE_CLASS;
+
+// device allowed to publish readings, as defined in the registry file
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSpec {
+    pub device_uuid: String,
+    // hex SHA-256 of the API token, like the output of `printf '%s' "$API_TOKEN" | sha256sum`
+    pub api_token_hash: String,
+    // features the device can publish
+    pub features: Vec<String>,
+    // optional secret key of the HMAC-SHA256 signatures of the device, see `SignaturePolicy`.
+    // Unlike API tokens it's stored in plain text, so the file must be readable only by the producer.
+    #[serde(default)]
+    pub signing_secret: String,
+    // class with the rate limits of the device, see `RateLimiter`
+    #[serde(default)]
+    pub class: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeviceRegistryFile {
+    devices: Vec<DeviceSpec>,
+}
+
+#[derive(Debug)]
+struct Device {
+    token_hash: [u8; 32],
+    features: Vec<String>,
+    signing_secret: Vec<u8>,
+    class: String,
+}
+
+// devices by uuid, with the hash of their API token.
+// Plain tokens are never stored, so the file can be shared with operators.
+#[derive(Debug, Default)]
+pub struct DeviceRegistry {
+    devices: HashMap<String, Device>,
+}
+
+impl DeviceRegistry {
+    // load devices from a JSON file with the format `{"devices": [...]}`
+    pub fn load(path: &str) -> Result<Self, DeviceRegistryError> {
+        info!(target: "app", "load - loading device registry from file {}", path);
+        let content = read_to_string(path).map_err(|err| DeviceRegistryError::Read(path.to_string(), err))?;
+        let file: DeviceRegistryFile =
+            serde_json::from_str(&content).map_err(|err| DeviceRegistryError::Parse(path.to_string(), err))?;
+        let mut devices = HashMap::new();
+        for spec in file.devices {
+            let mut token_hash = [0u8; 32];
+            hex::decode_to_slice(spec.api_token_hash.trim(), &mut token_hash).map_err(|_| {
+                DeviceRegistryError::InvalidDevice(
+                    spec.device_uuid.clone(),
+                    String::from("apiTokenHash must be a hex SHA-256 hash"),
+                )
+            })?;
+            let device = Device {
+                token_hash,
+                features: spec.features,
+                signing_secret: spec.signing_secret.into_bytes(),
+                class: spec.class.unwrap_or_else(|| DEFAULT_DEVICE_CLASS.to_string()),
+            };
+            // UUIDs are case-insensitive
+            if devices.insert(spec.device_uuid.to_lowercase(), device).is_some() {
+                return Err(DeviceRegistryError::InvalidDevice(
+                    spec.device_uuid,
+                    String::from("device is defined more than once"),
+                ));
+            }
+        }
+        debug!(target: "app", "load - {} devices in device registry", devices.len());
+        Ok(Self { devices })
+    }
+
+    // returns the reason why the reading must be rejected, if the device is unknown,
+    // the API token is wrong or the feature is not allowed for the device
+    pub fn verify(&self, device_uuid: &str, api_token: &str, feature_name: &str) -> Result<(), RejectReason> {
+        let Some(device) = self.devices.get(&device_uuid.to_lowercase()) else {
+            return Err(RejectReason::UnknownDevice(device_uuid.to_string()));
+        };
+        let token_hash = Sha256::digest(api_token.as_bytes());
+        if !bool::from(token_hash.as_slice().ct_eq(&device.token_hash)) {
+            return Err(RejectReason::InvalidToken(device_uuid.to_string()));
+        }
+        if !device.features.iter().any(|name| name == feature_name) {
+            return Err(RejectReason::FeatureNotAllowed(feature_name.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, device_uuid: &str) -> bool {
+        self.devices.contains_key(&device_uuid.to_lowercase())
+    }
+
+    // class of the device, the default one for unknown devices
+    pub fn device_class(&self, device_uuid: &str) -> &str {
+        self.devices
+            .get(&device_uuid.to_lowercase())
+            .map_or(DEFAULT_DEVICE_CLASS, |device| device.class.as_str())
+    }
+
+    // verifies the hex HMAC-SHA256 `signature` of `content` with the signing secret of the device
+    pub fn verify_signature(&self, device_uuid: &str, content: &str, signature: &str) -> Result<(), RejectReason> {
+        let Some(device) = self.devices.get(&device_uuid.to_lowercase()) else {
+            return Err(RejectReason::UnknownDevice(device_uuid.to_string()));
+        };
+        let invalid = || RejectReason::InvalidSignature(device_uuid.to_string());
+        if device.signing_secret.is_empty() {
+            return Err(invalid());
+        }
+        let signature = hex::decode(signature.trim()).map_err(|_| invalid())?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&device.signing_secret).expect("HMAC accepts keys of any length");
+        mac.update(content.as_bytes());
+        // constant-time comparison
+        mac.verify_slice(&signature).map_err(|_| invalid())
+    }
+}
+
+// device registry reloaded when its file changes, without restarting the producer.
+// Clones share the same registry.
+#[derive(Debug, Clone)]
+pub struct ReloadableDeviceRegistry {
+    path: String,
+    state: Arc<RwLock<(Arc<DeviceRegistry>, Option<SystemTime>)>>,
+}
+
+impl ReloadableDeviceRegistry {
+    pub fn load(path: &str) -> Result<Self, DeviceRegistryError> {
+        let modified = modified(path);
+        let registry = DeviceRegistry::load(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            state: Arc::new(RwLock::new((Arc::new(registry), modified))),
+        })
+    }
+
+    pub fn verify(&self, device_uuid: &str, api_token: &str, feature_name: &str) -> Result<(), RejectReason> {
+        let registry = Arc::clone(&self.state.read().unwrap().0);
+        registry.verify(device_uuid, api_token, feature_name)
+    }
+
+    pub fn verify_signature(&self, device_uuid: &str, content: &str, signature: &str) -> Result<(), RejectReason> {
+        let registry = Arc::clone(&self.state.read().unwrap().0);
+        registry.verify_signature(device_uuid, content, signature)
+    }
+
+    pub fn contains(&self, device_uuid: &str) -> bool {
+        self.state.read().unwrap().0.contains(device_uuid)
+    }
+
+    pub fn device_class(&self, device_uuid: &str) -> String {
+        let registry = Arc::clone(&self.state.read().unwrap().0);
+        registry.device_class(device_uuid).to_string()
+    }
+
+    // reloads the file if it was modified since the last load and returns true if reloaded.
+    // If the new file is not valid, the current registry is kept and the error is returned.
+    pub fn reload_if_modified(&self) -> Result<bool, DeviceRegistryError> {
+        let modified = modified(&self.path);
+        if modified == self.state.read().unwrap().1 {
+            return Ok(false);
+        }
+        let registry = DeviceRegistry::load(&self.path)?;
+        *self.state.write().unwrap() = (Arc::new(registry), modified);
+        info!(target: "app", "reload_if_modified - device registry reloaded from file {}", &self.path);
+        Ok(true)
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::RejectReason;
+    use crate::models::device_registry::{DeviceRegistry, ReloadableDeviceRegistry};
+    use crate::models::rate_limit::DEFAULT_DEVICE_CLASS;
+    use pretty_assertions::assert_eq;
+    use std::fs::{remove_file, write};
+    use std::time::{Duration, SystemTime};
+
+    const DEVICE_UUID: &str = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+    const API_TOKEN: &str = "473a4861-632b-4915-b01e-cf1d418966c6";
+    // printf '%s' "473a4861-632b-4915-b01e-cf1d418966c6" | sha256sum
+    const API_TOKEN_HASH: &str = "896c8db249671c4c68dff319d25dd859c4a0bde3efd1d201e66f61e2c9f9caa3";
+
+    fn registry_json(features: &str) -> String {
+        format!(
+            r#"{{"devices": [{{"deviceUuid": "{}", "apiTokenHash": "{}", "features": [{}]}}]}}"#,
+            DEVICE_UUID, API_TOKEN_HASH, features
+        )
+    }
+
+    #[test]
+    fn check_verify_device() {
+        let path = std::env::temp_dir().join("check_verify_device.json");
+        write(&path, registry_json(r#""temperature", "humidity""#)).unwrap();
+        let registry = DeviceRegistry::load(path.to_str().unwrap()).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(registry.verify(DEVICE_UUID, API_TOKEN, "temperature"), Ok(()));
+        assert_eq!(
+            registry.verify(&DEVICE_UUID.to_uppercase(), API_TOKEN, "humidity"),
+            Ok(())
+        );
+        assert_eq!(
+            registry.verify("0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60", API_TOKEN, "temperature"),
+            Err(RejectReason::UnknownDevice(
+                "0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60".to_string()
+            ))
+        );
+        assert_eq!(
+            registry.verify(DEVICE_UUID, "wrong", "temperature"),
+            Err(RejectReason::InvalidToken(DEVICE_UUID.to_string()))
+        );
+        assert_eq!(
+            registry.verify(DEVICE_UUID, API_TOKEN, "motion"),
+            Err(RejectReason::FeatureNotAllowed("motion".to_string()))
+        );
+        assert_eq!(registry.device_class(DEVICE_UUID), DEFAULT_DEVICE_CLASS);
+        assert!(registry.contains(&DEVICE_UUID.to_uppercase()));
+        assert!(!registry.contains("0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60"));
+    }
+
+    #[test]
+    fn check_reload_device_registry() {
+        let path = std::env::temp_dir().join("check_reload_device_registry.json");
+        write(&path, registry_json(r#""temperature""#)).unwrap();
+        let registry = ReloadableDeviceRegistry::load(path.to_str().unwrap()).unwrap();
+        let shared = registry.clone();
+        assert_eq!(registry.reload_if_modified().unwrap(), false);
+        assert!(shared.verify(DEVICE_UUID, API_TOKEN, "humidity").is_err());
+
+        // a new modification time, also on file systems with a coarse resolution
+        write(&path, registry_json(r#""temperature", "humidity""#)).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert_eq!(registry.reload_if_modified().unwrap(), true);
+        assert_eq!(shared.verify(DEVICE_UUID, API_TOKEN, "humidity"), Ok(()));
+
+        // an invalid file keeps the current registry
+        write(&path, "{").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(20)).unwrap();
+        assert!(registry.reload_if_modified().is_err());
+        assert_eq!(shared.verify(DEVICE_UUID, API_TOKEN, "humidity"), Ok(()));
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wrong_load_device_registry_file() {
+        let path = std::env::temp_dir().join("wrong_load_device_registry_file.json");
+        write(
+            &path,
+            format!(
+                r#"{{"devices": [{{"deviceUuid": "{}", "apiTokenHash": "plain-token", "features": []}}]}}"#,
+                DEVICE_UUID
+            ),
+        )
+        .unwrap();
+        let res = DeviceRegistry::load(path.to_str().unwrap());
+        remove_file(&path).unwrap();
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            format!(
+                "invalid device {} in device registry: apiTokenHash must be a hex SHA-256 hash",
+                DEVICE_UUID
+            )
+        );
+    }
+}
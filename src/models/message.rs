@@ -1,6 +1,10 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::models::payload_trait::PayloadTrait;
+use crate::models::output_codec::OutputCodec;
+use crate::models::payload_trait::{FeaturePayload, FeatureValue, PayloadTrait};
+use crate::models::schema_version::SchemaVersion;
+use crate::models::token_policy::ApiTokenPolicy;
 use crate::models::topic::Topic;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -9,34 +13,202 @@ pub struct Message<T>
 where
     T: PayloadTrait + Sized + Serialize,
 {
+    pub schema_version: SchemaVersion,
+    // empty if dropped by the apiToken policy of the queue
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub api_token: String,
     pub device_uuid: String,
     pub feature_uuid: String,
     pub topic: Topic,
     pub payload: T,
+    // canonical unit of the value, present only if the device sent a unit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    // unit sent by the device, before the conversion to the canonical one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+    pub received_at: DateTime<Utc>,
+    // true if deviceUuid of the payload didn't match the device id of the topic
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub device_mismatch: bool,
+    // true if the reading was signed by the device with a valid signature
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub signature_verified: bool,
 }
 
 impl<T> Message<T>
 where
     T: PayloadTrait + Sized + Serialize,
 {
-    pub fn new(api_token: String, device_uuid: String, feature_uuid: String, topic: Topic, payload: T) -> Message<T> {
+    pub fn new(
+        api_token: String,
+        device_uuid: String,
+        feature_uuid: String,
+        topic: Topic,
+        payload: T,
+        timestamp: Option<DateTime<Utc>>,
+        received_at: DateTime<Utc>,
+    ) -> Message<T> {
         Self {
+            schema_version: SchemaVersion::LATEST,
             api_token,
             device_uuid,
             feature_uuid,
             topic,
             payload,
+            unit: None,
+            original_unit: None,
+            timestamp,
+            received_at,
+            device_mismatch: false,
+            signature_verified: false,
         }
     }
+
+    pub fn with_units(mut self, unit: Option<String>, original_unit: Option<String>) -> Message<T> {
+        self.unit = unit;
+        self.original_unit = original_unit;
+        self
+    }
+
+    pub fn with_device_mismatch(mut self, device_mismatch: bool) -> Message<T> {
+        self.device_mismatch = device_mismatch;
+        self
+    }
+
+    pub fn with_signature_verified(mut self, signature_verified: bool) -> Message<T> {
+        self.signature_verified = signature_verified;
+        self
+    }
     pub fn new_as_json(
         api_token: String,
         device_uuid: String,
         feature_uuid: String,
         topic: Topic,
         payload: T,
+        timestamp: Option<DateTime<Utc>>,
+        received_at: DateTime<Utc>,
     ) -> String {
-        let message = Self::new(api_token, device_uuid, feature_uuid, topic, payload);
+        let message = Self::new(
+            api_token,
+            device_uuid,
+            feature_uuid,
+            topic,
+            payload,
+            timestamp,
+            received_at,
+        );
         serde_json::to_string(&message).unwrap()
     }
 }
+
+// message to publish, encoded for each route with its codec and schema version
+#[derive(Debug, Clone)]
+pub enum Envelope {
+    Message(Message<FeaturePayload>),
+    Batch(BatchMessage),
+}
+
+impl Envelope {
+    pub fn topic(&self) -> &Topic {
+        match self {
+            Envelope::Message(message) => &message.topic,
+            Envelope::Batch(message) => &message.topic,
+        }
+    }
+
+    pub fn device_mismatch(&self) -> bool {
+        match self {
+            Envelope::Message(message) => message.device_mismatch,
+            Envelope::Batch(message) => message.device_mismatch,
+        }
+    }
+
+    pub fn received_at(&self) -> DateTime<Utc> {
+        match self {
+            Envelope::Message(message) => message.received_at,
+            Envelope::Batch(message) => message.received_at,
+        }
+    }
+
+    // timestamp of the reading or of the most recent value of the batch
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Envelope::Message(message) => message.timestamp,
+            Envelope::Batch(message) => message.values.iter().filter_map(|value| value.timestamp).max(),
+        }
+    }
+
+    // batches can't be signed
+    pub fn signature_verified(&self) -> bool {
+        match self {
+            Envelope::Message(message) => message.signature_verified,
+            Envelope::Batch(_) => false,
+        }
+    }
+
+    // removes the apiToken, so it can be forwarded to each queue with its policy
+    pub fn take_api_token(&mut self) -> String {
+        match self {
+            Envelope::Message(message) => std::mem::take(&mut message.api_token),
+            Envelope::Batch(message) => std::mem::take(&mut message.api_token),
+        }
+    }
+
+    // sets the apiToken forwarded by `policy` and returns the value of the AMQP header, if any
+    pub fn forward_api_token(&mut self, api_token: &str, policy: ApiTokenPolicy, hash_key: &[u8]) -> Option<String> {
+        let forwarded = policy.apply(api_token, hash_key);
+        match self {
+            Envelope::Message(message) => message.api_token = forwarded.body,
+            Envelope::Batch(message) => message.api_token = forwarded.body,
+        }
+        forwarded.header
+    }
+
+    // batch envelopes didn't exist before version 2, so they are always encoded with the latest version.
+    // `ProcessingConfig::new` rejects batch envelopes when a queue uses version 1
+    pub fn encode(&self, codec: OutputCodec, version: SchemaVersion) -> Vec<u8> {
+        match self {
+            Envelope::Message(message) => codec.encode_message(message, version),
+            Envelope::Batch(message) => codec.encode_batch(message),
+        }
+    }
+
+    // like `encode`, but replaces the content of a reused buffer instead of allocating a new one
+    pub fn encode_into(&self, codec: OutputCodec, version: SchemaVersion, out: &mut Vec<u8>) {
+        out.clear();
+        match self {
+            Envelope::Message(message) => codec.encode_message_into(message, version, out),
+            Envelope::Batch(message) => codec.encode_batch_into(message, out),
+        }
+    }
+}
+
+// single message with all values of a batch, sent when batches are not expanded
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchMessage {
+    pub schema_version: SchemaVersion,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub api_token: String,
+    pub device_uuid: String,
+    pub feature_uuid: String,
+    pub topic: Topic,
+    pub values: Vec<BatchValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_unit: Option<String>,
+    pub received_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub device_mismatch: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchValue {
+    pub value: FeatureValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+}
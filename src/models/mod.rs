@@ -1,69 +1,457 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
 use tracing::{debug, error};
 
-use crate::models::message::Message;
-use crate::models::notification::Notification;
-use crate::models::payload_trait::{
-    AirPressure, AirQuality, Humidity, Light, Motion, Online, PayloadTrait, Temperature,
-};
+use crate::errors::message_error::{MessageError, RejectReason};
+use crate::models::batch::BatchMode;
+use crate::models::message::{BatchMessage, BatchValue, Envelope, Message};
+use crate::models::notification::{BatchNotification, MultiNotification, Notification};
+use crate::models::payload_format::PayloadFormat;
+use crate::models::payload_trait::{FeaturePayload, FeatureValue};
+use crate::models::processing_config::ProcessingConfig;
+use crate::models::rate_limit::DEFAULT_DEVICE_CLASS;
+use crate::models::schema_version::SchemaVersion;
+use crate::models::sensor_registry::FeatureSpec;
 use crate::models::topic::Topic;
 
+pub mod batch;
+pub mod coercion;
+pub mod device_check;
+pub mod device_registry;
 pub mod message;
 pub mod notification;
+pub mod output_codec;
+pub mod payload_format;
+pub mod payload_limits;
 pub mod payload_trait;
+pub mod processing_config;
+pub mod proto;
+pub mod quarantine;
+pub mod rate_limit;
+pub mod rejected;
+pub mod route;
+pub mod schema_version;
+pub mod sensor_registry;
+pub mod signature;
+pub mod timestamp;
+pub mod token_policy;
 pub mod topic;
 
-pub fn get_msg_byte(topic: &Topic, payload_str: &str) -> Vec<u8> {
-    debug!(target: "app", "payload_str: {}", payload_str);
-    let msg_byte: Vec<u8> = match topic.feature_name.as_str() {
-        "temperature" => message_payload_to_bytes::<Temperature>(payload_str, topic),
-        "humidity" => message_payload_to_bytes::<Humidity>(payload_str, topic),
-        "light" => message_payload_to_bytes::<Light>(payload_str, topic),
-        "motion" => message_payload_to_bytes::<Motion>(payload_str, topic),
-        "airquality" => message_payload_to_bytes::<AirQuality>(payload_str, topic),
-        "airpressure" => message_payload_to_bytes::<AirPressure>(payload_str, topic),
-        "online" => message_payload_to_bytes::<Online>(payload_str, topic),
-        _ => vec![],
+// feature name of composite topics, like `sensors/<id>/multi`,
+// whose payload contains readings of several features
+pub const MULTI_FEATURE_NAME: &str = "multi";
+
+// returns one result for each reading in the JSON payload,
+// so one for single readings and many for composite topics or batches
+pub fn get_msg_bytes(
+    topic: &Topic,
+    payload_str: &str,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Vec<Result<Vec<u8>, MessageError>> {
+    debug!(target: "app", "payload_str: {}", config.redacted_fields.payload(payload_str));
+    get_msg_bytes_with_format(topic, payload_str.as_bytes(), PayloadFormat::Json, received_at, config)
+}
+
+// like `get_msg_bytes`, for payloads encoded in any supported format
+pub fn get_msg_bytes_with_format(
+    topic: &Topic,
+    payload: &[u8],
+    format: PayloadFormat,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Vec<Result<Vec<u8>, MessageError>> {
+    get_envelopes_with_format(topic, payload, format, received_at, config)
+        .into_iter()
+        .map(|result| result.map(|envelope| envelope.encode(config.output_codec, config.schema_version)))
+        .collect()
+}
+
+// like `get_msg_bytes_with_format`, but messages are not encoded yet,
+// so they can be published to many routes with different versions
+pub fn get_envelopes_with_format(
+    topic: &Topic,
+    payload: &[u8],
+    format: PayloadFormat,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Vec<Result<Envelope, MessageError>> {
+    if topic.feature_name == MULTI_FEATURE_NAME {
+        return multi_payload_to_envelopes(payload, format, topic, received_at, config);
+    }
+    match config.registry.get(topic.feature_name.as_str()) {
+        Some(feature) => feature_payload_to_envelopes(payload, format, topic, feature, received_at, config),
+        None => {
+            error!(target: "app", "get_envelopes_with_format - unknown feature {}", &topic.feature_name);
+            vec![Err(MessageError::UnknownFeature(topic.feature_name.clone()))]
+        }
+    }
+}
+
+// checks the quarantine and the rate limits of the device and feature of the topic,
+// before decoding the payload, so misbehaving devices are dropped as early as possible
+pub fn check_quarantine_and_rate_limit(
+    topic: &Topic,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Result<(), MessageError> {
+    let dropped = |reason: RejectReason| {
+        // logged at debug level, to not flood also the logs
+        debug!(target: "app", "check_quarantine_and_rate_limit - dropped message from device {}, reason = {}", &topic.device_id, &reason);
+        MessageError::Rejected {
+            feature: topic.feature_name.clone(),
+            reason,
+        }
+    };
+    if config.quarantine.is_quarantined(&topic.device_id, received_at) {
+        return Err(dropped(RejectReason::Quarantined(topic.device_id.clone())));
+    }
+    let Some(rate_limiter) = &config.rate_limiter else {
+        return Ok(());
     };
-    msg_byte
+    let class = match &config.devices {
+        Some(devices) => devices.device_class(&topic.device_id),
+        None => DEFAULT_DEVICE_CLASS.to_string(),
+    };
+    rate_limiter
+        .check(
+            &topic.device_id,
+            &topic.feature_name,
+            &class,
+            received_at,
+            &config.quarantine,
+        )
+        .map_err(dropped)
 }
 
-fn message_payload_to_bytes<'a, T>(payload_str: &'a str, topic: &Topic) -> Vec<u8>
-where
-    T: Deserialize<'a> + Serialize + Clone + PayloadTrait + Sized,
-{
+// returns the message of a single reading, batches are not supported
+pub fn get_msg_byte(
+    topic: &Topic,
+    payload_str: &str,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, MessageError> {
+    debug!(target: "app", "payload_str: {}", config.redacted_fields.payload(payload_str));
+    match config.registry.get(topic.feature_name.as_str()) {
+        Some(feature) => message_payload_to_bytes(payload_str, topic, feature, received_at, config),
+        None => {
+            error!(target: "app", "get_msg_byte - unknown feature {}", &topic.feature_name);
+            Err(MessageError::UnknownFeature(topic.feature_name.clone()))
+        }
+    }
+}
+
+fn message_payload_to_bytes(
+    payload_str: &str,
+    topic: &Topic,
+    feature: &FeatureSpec,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, MessageError> {
+    if is_empty(payload_str.as_bytes()) {
+        return Err(MessageError::EmptyMessageError);
+    }
     // deserialize to a Notification (with turbofish operator "::<Notification>")
-    let parsed_result = serde_json::from_str::<Notification<T>>(payload_str);
+    let parsed_result = serde_json::from_str::<Notification<FeaturePayload>>(payload_str);
     match parsed_result {
-        Ok(val) => {
-            debug!(target: "app", "message_payload_to_bytes - parsed from JSON string, returning as byte array");
-            let serialized = Message::<T>::new_as_json(
-                val.api_token.clone(),
-                val.device_uuid.clone(),
-                val.feature_uuid.clone(),
-                topic.clone(),
-                val.payload,
-            );
-            serialized.into_bytes()
+        Ok(val) => notification_to_envelope(val, topic, feature, received_at, config)
+            .map(|envelope| envelope.encode(config.output_codec, config.schema_version)),
+        Err(err) => {
+            error!(target: "app", "message_payload_to_bytes - cannot parse JSON from string. Err = {:?}", &err);
+            Err(MessageError::from(err))
+        }
+    }
+}
+
+// payload of a feature topic can be a single reading or a batch of readings
+fn feature_payload_to_envelopes(
+    payload: &[u8],
+    format: PayloadFormat,
+    topic: &Topic,
+    feature: &FeatureSpec,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Vec<Result<Envelope, MessageError>> {
+    if is_empty(payload) {
+        return vec![Err(MessageError::EmptyMessageError)];
+    }
+    let single_err = match format.decode::<Notification<FeaturePayload>>(payload) {
+        Ok(val) => return vec![notification_to_envelope(val, topic, feature, received_at, config)],
+        Err(err) => err,
+    };
+    match format.decode::<BatchNotification>(payload) {
+        Ok(val) => batch_payload_to_envelopes(val, topic, feature, received_at, config),
+        Err(batch_err) => {
+            error!(target: "app", "feature_payload_to_envelopes - cannot decode {} payload. Err = {:?}, as batch Err = {:?}", format, &single_err, &batch_err);
+            // without payload it's more likely a batch, so report why it's not a valid one
+            match MessageError::from(single_err) {
+                MessageError::MissingField(field) if field == "payload" => vec![Err(MessageError::from(batch_err))],
+                err => vec![Err(err)],
+            }
+        }
+    }
+}
+
+fn multi_payload_to_envelopes(
+    payload: &[u8],
+    format: PayloadFormat,
+    topic: &Topic,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Vec<Result<Envelope, MessageError>> {
+    if is_empty(payload) {
+        return vec![Err(MessageError::EmptyMessageError)];
+    }
+    let parsed_result = format.decode::<MultiNotification>(payload);
+    match parsed_result {
+        Ok(val) if !val.readings.is_empty() => val
+            .into_notifications()
+            .map(|(feature_name, notification)| {
+                // every reading is sent as if it was received on its own feature topic
+                let reading_topic = Topic {
+                    feature_name: feature_name.into_owned(),
+                    ..topic.clone()
+                };
+                match config.registry.get(reading_topic.feature_name.as_str()) {
+                    Some(feature) => notification_to_envelope(notification, &reading_topic, feature, received_at, config),
+                    None => {
+                        error!(target: "app", "multi_payload_to_envelopes - unknown feature {}", &reading_topic.feature_name);
+                        Err(MessageError::UnknownFeature(reading_topic.feature_name))
+                    }
+                }
+            })
+            .collect(),
+        Ok(_) => {
+            error!(target: "app", "multi_payload_to_envelopes - no readings in multi payload");
+            vec![Err(MessageError::EmptyMessageError)]
         }
         Err(err) => {
-            error!(target: "app", "message_payload_to_bytes - cannot parse JSON from string, returning empty data. Err = {:?}", &err);
-            vec![]
+            error!(target: "app", "multi_payload_to_envelopes - cannot decode {} payload. Err = {:?}", format, &err);
+            vec![Err(MessageError::from(err))]
+        }
+    }
+}
+
+fn batch_payload_to_envelopes(
+    val: BatchNotification<'_>,
+    topic: &Topic,
+    feature: &FeatureSpec,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Vec<Result<Envelope, MessageError>> {
+    let policy = &config.batch_policy;
+    if val.values.is_empty() {
+        error!(target: "app", "batch_payload_to_envelopes - no values in batch payload");
+        return vec![Err(MessageError::EmptyMessageError)];
+    }
+    if let Err(reason) = policy.check_size(val.values.len()) {
+        return vec![Err(rejected(topic, feature, reason))];
+    }
+    // checked once for the whole batch, so an invalid device is rejected with a single error
+    // and expanded values reuse the result
+    let (device_uuid, device_mismatch) = match check_device(&val.device_uuid, &val.api_token, topic, feature, config) {
+        Ok(checked) => checked,
+        Err(err) => return vec![Err(err)],
+    };
+    debug!(target: "app", "batch_payload_to_envelopes - {} values in batch, mode = {:?}", val.values.len(), policy.mode);
+    let (api_token, feature_uuid, original_unit) = (
+        val.api_token.to_string(),
+        val.feature_uuid.to_string(),
+        val.unit.clone(),
+    );
+    let mut batch_values = Vec::new();
+    let mut results = Vec::new();
+    for notification in val.into_notifications() {
+        let checked = notification
+            .timestamp
+            .map_or(Ok(()), |ts| policy.check_age(ts, received_at))
+            .map_err(|reason| rejected(topic, feature, reason));
+        match policy.mode {
+            BatchMode::Expand => results.push(checked.and_then(|_| {
+                let device = (device_uuid.clone(), device_mismatch);
+                device_notification_to_envelope(notification, device, topic, feature, received_at, config)
+            })),
+            BatchMode::Envelope => {
+                // values of batches are never signed, so they are rejected in strict mode
+                let checked = checked
+                    .and_then(|_| verify_signature(&notification, topic, feature, received_at, config))
+                    .and_then(|_| validate_notification(&notification, topic, feature, received_at, config));
+                match checked {
+                    Ok((value, timestamp)) => batch_values.push(BatchValue { value, timestamp }),
+                    Err(err) => results.push(Err(err)),
+                }
+            }
         }
     }
+    if !batch_values.is_empty() {
+        let (unit, original_unit) = units(feature, original_unit);
+        let message = BatchMessage {
+            schema_version: SchemaVersion::LATEST,
+            api_token,
+            device_uuid,
+            feature_uuid,
+            topic: topic.clone(),
+            values: batch_values,
+            unit,
+            original_unit,
+            received_at,
+            device_mismatch,
+        };
+        // the envelope comes first, followed by errors of rejected values
+        results.insert(0, Ok(Envelope::Batch(message)));
+    }
+    results
+}
+
+fn notification_to_envelope(
+    val: Notification<'_, FeaturePayload>,
+    topic: &Topic,
+    feature: &FeatureSpec,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Result<Envelope, MessageError> {
+    let device = check_device(&val.device_uuid, &val.api_token, topic, feature, config)?;
+    device_notification_to_envelope(val, device, topic, feature, received_at, config)
+}
+
+// like `notification_to_envelope`, with the result of `check_device` already available,
+// e.g. for values of a batch, where the device is checked once for the whole batch
+fn device_notification_to_envelope(
+    val: Notification<'_, FeaturePayload>,
+    (device_uuid, device_mismatch): (String, bool),
+    topic: &Topic,
+    feature: &FeatureSpec,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Result<Envelope, MessageError> {
+    // the signature is made with the secret of the deviceUuid of the payload,
+    // so it doesn't prove the identity of a different forwarded uuid
+    let signature_verified = verify_signature(&val, topic, feature, received_at, config)?
+        && device_uuid.eq_ignore_ascii_case(&val.device_uuid);
+    let (value, timestamp) = validate_notification(&val, topic, feature, received_at, config)?;
+    debug!(target: "app", "notification_to_envelope - reading is valid, returning as envelope");
+    let (unit, original_unit) = units(feature, val.unit);
+    let message = Message::<FeaturePayload>::new(
+        val.api_token.into_owned(),
+        device_uuid,
+        val.feature_uuid.into_owned(),
+        topic.clone(),
+        FeaturePayload { value },
+        timestamp,
+        received_at,
+    )
+    .with_units(unit, original_unit)
+    .with_device_mismatch(device_mismatch)
+    .with_signature_verified(signature_verified);
+    Ok(Envelope::Message(message))
+}
+
+// verifies the device with the device id of the topic and against the device registry, if any.
+// Returns the device uuid to forward and if it didn't match the topic.
+// Credentials are verified for the forwarded uuid, so a device cannot publish as another one
+// replacing its deviceUuid with the device id of the topic.
+fn check_device(
+    device_uuid: &str,
+    api_token: &str,
+    topic: &Topic,
+    feature: &FeatureSpec,
+    config: &ProcessingConfig,
+) -> Result<(String, bool), MessageError> {
+    let (device_uuid, device_mismatch) = config
+        .device_mismatch_action
+        .apply(&topic.device_id, device_uuid)
+        .map_err(|reason| rejected(topic, feature, reason))?;
+    if let Some(devices) = &config.devices {
+        devices
+            .verify(device_uuid, api_token, &feature.name)
+            .map_err(|reason| rejected(topic, feature, reason))?;
+    }
+    Ok((device_uuid.to_string(), device_mismatch))
+}
+
+// returns true if the reading is signed with a valid signature
+fn verify_signature(
+    val: &Notification<'_, FeaturePayload>,
+    topic: &Topic,
+    feature: &FeatureSpec,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Result<bool, MessageError> {
+    config
+        .signature_policy
+        .verify(val, received_at, config.devices.as_ref())
+        .map_err(|reason| rejected(topic, feature, reason))
+}
+
+// returns the normalized value and the timestamp to forward
+fn validate_notification(
+    val: &Notification<'_, FeaturePayload>,
+    topic: &Topic,
+    feature: &FeatureSpec,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Result<(FeatureValue, Option<DateTime<Utc>>), MessageError> {
+    let value = config
+        .coercion
+        .coerce(feature.map_value(val.payload.value.clone()), feature.value_type);
+    feature
+        .normalize(value, val.unit.as_deref())
+        .and_then(|value| feature.check_value(value))
+        .and_then(|value| Ok((value, config.timestamp_policy.apply(val.timestamp, received_at)?)))
+        .map_err(|reason| rejected(topic, feature, reason))
+}
+
+fn is_empty(payload: &[u8]) -> bool {
+    payload.iter().all(u8::is_ascii_whitespace)
+}
+
+fn rejected(topic: &Topic, feature: &FeatureSpec, reason: RejectReason) -> MessageError {
+    error!(target: "app", "rejected - rejected {} reading from device {}, reason = {}", &feature.name, &topic.device_id, &reason);
+    MessageError::Rejected {
+        feature: feature.name.clone(),
+        reason,
+    }
+}
+
+// canonical and original units, recorded only if the device sent a unit
+fn units(feature: &FeatureSpec, original_unit: Option<String>) -> (Option<String>, Option<String>) {
+    match original_unit {
+        Some(original_unit) => (feature.unit.clone(), Some(original_unit)),
+        None => (None, None),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::config::init;
-    use crate::models::get_msg_byte;
+    use crate::errors::message_error::{MessageError, RejectReason};
+    use crate::models::batch::BatchMode;
+    use crate::models::coercion::CoercionMode;
+    use crate::models::device_check::DeviceMismatchAction;
+    use crate::models::device_registry::ReloadableDeviceRegistry;
+    use crate::models::payload_format::PayloadFormat;
+    use crate::models::payload_trait::{FeaturePayload, FeatureValue};
+    use crate::models::processing_config::ProcessingConfig;
+    use crate::models::schema_version::SchemaVersion;
+    use crate::models::sensor_registry::{FeatureSpec, ValueType};
+    use crate::models::signature::{SignatureMode, SignaturePolicy, signed_content};
+    use crate::models::timestamp::SkewAction;
     use crate::models::topic::Topic;
+    use crate::models::{get_msg_byte, get_msg_bytes, get_msg_bytes_with_format};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use hmac::{Hmac, Mac};
     use pretty_assertions::assert_eq;
     use serde::Serialize;
     use serde_json::json;
+    use sha2::Sha256;
     use std::str::from_utf8;
     use tracing::debug;
 
+    const RECEIVED_AT: &str = "2025-12-25T10:30:00Z";
+
+    fn received_at() -> DateTime<Utc> {
+        RECEIVED_AT.parse().unwrap()
+    }
+
     fn get_expected_json_string<T: Serialize>(
         device_uuid: &str,
         feature_uuid: &str,
@@ -72,6 +460,7 @@ mod tests {
     ) -> String {
         let api_token = "473a4861-632b-4915-b01e-cf1d418966c6";
         json!({
+            "schemaVersion": 2,
             "apiToken": api_token,
             "deviceUuid": device_uuid,
             "featureUuid": feature_uuid,
@@ -82,7 +471,8 @@ mod tests {
             },
             "payload": {
                 "value": value
-            }
+            },
+            "receivedAt": RECEIVED_AT
         })
         .to_string()
     }
@@ -91,6 +481,7 @@ mod tests {
     fn ok_get_msg_byte_sensors() {
         // init logger and env
         let _ = init();
+        let config = ProcessingConfig::default();
 
         let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
         let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
@@ -103,7 +494,7 @@ mod tests {
             let topic: Topic = Topic::new(format!("sensors/{}/{}", device_uuid, sensor_type).as_str());
             let expected_value = get_expected_json_string::<f64>(device_uuid, feature_uuid, VALUE_FLOAT, &topic);
 
-            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str());
+            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config).unwrap();
             let result = from_utf8(msg_byte_arr.as_slice()).unwrap();
 
             debug!(target: "app", "result = {}", result);
@@ -115,7 +506,7 @@ mod tests {
             let topic: Topic = Topic::new(format!("sensors/{}/{}", device_uuid, sensor_type).as_str());
             let expected_value = get_expected_json_string::<i64>(device_uuid, feature_uuid, VALUE_INT, &topic);
 
-            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str());
+            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config).unwrap();
             let result = from_utf8(msg_byte_arr.as_slice()).unwrap();
 
             debug!(target: "app", "result = {}", result);
@@ -126,56 +517,760 @@ mod tests {
         // unknown sensor type
         let topic: Topic = Topic::new(format!("sensors/{}/unknown", device_uuid).as_str());
         let expected_value = get_expected_json_string::<i64>(device_uuid, feature_uuid, VALUE_INT, &topic);
-        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str());
-        assert_eq!(msg_byte_arr.len(), 0);
+        let res = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::UnknownFeature(String::from("unknown")).to_string()
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_byte_more_sensors() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        const FLOAT_SENSORS: &[&str] = &[
+            "co2", "voc", "pm25", "pm10", "noise", "uvindex", "battery", "voltage", "power", "energy",
+        ];
+        const INT_SENSORS: &[&str] = &["waterleak", "doorcontact"];
+        const VALUE_FLOAT: f64 = 12.0;
+        const VALUE_INT: i64 = 1;
+
+        for sensor_type in FLOAT_SENSORS.iter() {
+            let topic: Topic = Topic::new(format!("sensors/{}/{}", device_uuid, sensor_type).as_str());
+            let expected_value = get_expected_json_string::<f64>(device_uuid, feature_uuid, VALUE_FLOAT, &topic);
+
+            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config).unwrap();
+            let result = from_utf8(msg_byte_arr.as_slice()).unwrap();
+            assert_eq!(result.to_string(), expected_value);
+        }
+
+        for sensor_type in INT_SENSORS.iter() {
+            let topic: Topic = Topic::new(format!("sensors/{}/{}", device_uuid, sensor_type).as_str());
+            let expected_value = get_expected_json_string::<i64>(device_uuid, feature_uuid, VALUE_INT, &topic);
+
+            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config).unwrap();
+            let result = from_utf8(msg_byte_arr.as_slice()).unwrap();
+            assert_eq!(result.to_string(), expected_value);
+
+            // a float value is not allowed for int sensors
+            let float_value = get_expected_json_string::<f64>(device_uuid, feature_uuid, 0.5, &topic);
+            let res = get_msg_byte(&topic, float_value.as_str(), received_at(), &config);
+            assert_eq!(
+                res.err().unwrap().to_string(),
+                format!("Rejected {} reading error: value is not of type int", sensor_type)
+            );
+        }
     }
 
     #[test]
     fn wrong_get_msg_byte_unknown_sensor() {
         // init logger and env
         let _ = init();
+        let config = ProcessingConfig::default();
 
         let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
         let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
         // unknown sensor type
         let topic: Topic = Topic::new(format!("sensors/{}/unknown_type", device_uuid).as_str());
         debug!(target: "app", "Topic = {}", &topic);
-        let msg_byte_arr: Vec<u8> = get_msg_byte(
+        let res = get_msg_byte(
             &topic,
             get_expected_json_string::<i64>(device_uuid, feature_uuid, 1, &topic).as_str(),
+            received_at(),
+            &config,
+        );
+        // for unknown sensor type, get_msg_byte returns an UnknownFeature error
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::UnknownFeature(String::from("unknown_type")).to_string()
         );
-        // for unknown sensor type, get_msg_byte returns an empty Vec<u8>
-        assert_eq!(msg_byte_arr.len(), 0);
     }
 
     #[test]
     fn wrong_get_msg_byte_bad_json_message() {
         // init logger and env
         let _ = init();
+        let config = ProcessingConfig::default();
 
         let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
         let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
         // create a message with a bad JSON payload
-        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, "{\"deviceUuid\": \"1234\", 12}");
-        // for bad JSON payloads, get_msg_byte returns an empty Vec<u8>
-        assert_eq!(msg_byte_arr.len(), 0);
+        let res = get_msg_byte(&topic, "{\"deviceUuid\": \"1234\", 12}", received_at(), &config);
+        // for bad JSON payloads, get_msg_byte returns an InvalidJson error with the position
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::InvalidJson {
+                line: 1,
+                column: 24,
+                reason: String::from("key must be a string"),
+            }
+            .to_string()
+        );
+
+        // empty payload
+        let res = get_msg_byte(&topic, "", received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::EmptyMessageError.to_string()
+        );
     }
 
     #[test]
     fn wrong_get_msg_byte_bad_value_format() {
         // init logger and env
         let _ = init();
+        let config = ProcessingConfig::default();
 
         let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
         let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
         let topic: Topic = Topic::new(format!("sensors/{}/{}", device_uuid, "motion").as_str());
-        // create a message with an int value, instead of a float as required by 'temperature'
+        // create a message with a float value, instead of an int as required by 'motion'
         let expected_value = get_expected_json_string::<f64>(device_uuid, feature_uuid, 5.0, &topic);
-        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str());
-        let result = from_utf8(msg_byte_arr.as_slice()).unwrap();
+        let res = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config);
+        // for bad value types, get_msg_byte rejects the reading
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "motion".to_string(),
+                reason: RejectReason::TypeMismatch(ValueType::Int),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn check_get_msg_byte_device_mismatch() {
+        // init logger and env
+        let _ = init();
+        let topic_device_id = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let payload_device_uuid = "0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", topic_device_id).as_str());
+        let payload = get_expected_json_string::<f64>(payload_device_uuid, feature_uuid, 21.5, &topic);
+        let decode = |bytes: Vec<u8>| serde_json::from_slice::<serde_json::Value>(&bytes).unwrap();
+
+        // by default the reading is forwarded with a flag
+        let config = ProcessingConfig::default();
+        let message = decode(get_msg_byte(&topic, payload.as_str(), received_at(), &config).unwrap());
+        assert_eq!(message["deviceUuid"], payload_device_uuid);
+        assert_eq!(message["deviceMismatch"], true);
+
+        let config = ProcessingConfig {
+            device_mismatch_action: DeviceMismatchAction::Overwrite,
+            ..ProcessingConfig::default()
+        };
+        let message = decode(get_msg_byte(&topic, payload.as_str(), received_at(), &config).unwrap());
+        assert_eq!(message["deviceUuid"], topic_device_id);
+        assert_eq!(message["deviceMismatch"], true);
+
+        let config = ProcessingConfig {
+            device_mismatch_action: DeviceMismatchAction::Reject,
+            ..ProcessingConfig::default()
+        };
+        let res = get_msg_byte(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "temperature".to_string(),
+                reason: RejectReason::DeviceMismatch(topic_device_id.to_string(), payload_device_uuid.to_string()),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn check_get_msg_byte_device_registry() {
+        // init logger and env
+        let _ = init();
+        let path = std::env::temp_dir().join("check_get_msg_byte_device_registry.json");
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        // SHA-256 of the API token used by get_expected_json_string
+        std::fs::write(
+            &path,
+            json!({"devices": [{
+                "deviceUuid": device_uuid,
+                "apiTokenHash": "896c8db249671c4c68dff319d25dd859c4a0bde3efd1d201e66f61e2c9f9caa3",
+                "features": ["temperature"]
+            }]})
+            .to_string(),
+        )
+        .unwrap();
+        let config = ProcessingConfig {
+            devices: Some(ReloadableDeviceRegistry::load(path.to_str().unwrap()).unwrap()),
+            ..ProcessingConfig::default()
+        };
+        std::fs::remove_file(&path).unwrap();
+
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
+        let expected_value = get_expected_json_string::<f64>(device_uuid, feature_uuid, 21.5, &topic);
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config).unwrap();
+        assert_eq!(from_utf8(msg_byte_arr.as_slice()).unwrap(), expected_value);
+
+        // feature not declared for the device
+        let topic: Topic = Topic::new(format!("sensors/{}/humidity", device_uuid).as_str());
+        let payload = get_expected_json_string::<f64>(device_uuid, feature_uuid, 48.0, &topic);
+        let res = get_msg_byte(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "humidity".to_string(),
+                reason: RejectReason::FeatureNotAllowed("humidity".to_string()),
+            }
+            .to_string()
+        );
+
+        // wrong API token
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
+        let payload = expected_value.replace("473a4861-632b-4915-b01e-cf1d418966c6", "stolen");
+        let res = get_msg_byte(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "temperature".to_string(),
+                reason: RejectReason::InvalidToken(device_uuid.to_string()),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn wrong_get_msg_byte_device_registry_mismatch() {
+        // init logger and env
+        let _ = init();
+        let path = std::env::temp_dir().join("wrong_get_msg_byte_device_registry_mismatch.json");
+        let device_a = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let device_b = "0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        // only device A has the API token used by get_expected_json_string
+        std::fs::write(
+            &path,
+            json!({"devices": [
+                {
+                    "deviceUuid": device_a,
+                    "apiTokenHash": "896c8db249671c4c68dff319d25dd859c4a0bde3efd1d201e66f61e2c9f9caa3",
+                    "features": ["temperature"]
+                },
+                {"deviceUuid": device_b, "apiTokenHash": "0".repeat(64), "features": ["temperature"]}
+            ]})
+            .to_string(),
+        )
+        .unwrap();
+        let devices = ReloadableDeviceRegistry::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // credentials of device A on the topic of device B
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_b).as_str());
+        let payload = get_expected_json_string::<f64>(device_a, feature_uuid, 21.5, &topic);
+
+        // the uuid of the topic would be forwarded, so the credentials of B are required
+        let config = ProcessingConfig {
+            devices: Some(devices.clone()),
+            device_mismatch_action: DeviceMismatchAction::Overwrite,
+            ..ProcessingConfig::default()
+        };
+        let res = get_msg_byte(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "temperature".to_string(),
+                reason: RejectReason::InvalidToken(device_b.to_string()),
+            }
+            .to_string()
+        );
+
+        // the uuid of device A is forwarded, flagged
+        let config = ProcessingConfig {
+            devices: Some(devices),
+            device_mismatch_action: DeviceMismatchAction::Flag,
+            ..ProcessingConfig::default()
+        };
+        let msg_byte_arr = get_msg_byte(&topic, payload.as_str(), received_at(), &config).unwrap();
+        let message: serde_json::Value = serde_json::from_slice(&msg_byte_arr).unwrap();
+        assert_eq!(message["deviceUuid"], device_a);
+        assert_eq!(message["deviceMismatch"], true);
+    }
+
+    #[test]
+    fn ok_get_msg_byte_signed() {
+        // init logger and env
+        let _ = init();
+        let path = std::env::temp_dir().join("ok_get_msg_byte_signed.json");
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        std::fs::write(
+            &path,
+            json!({"devices": [{
+                "deviceUuid": device_uuid,
+                "apiTokenHash": "896c8db249671c4c68dff319d25dd859c4a0bde3efd1d201e66f61e2c9f9caa3",
+                "features": ["temperature"],
+                "signingSecret": "device-s3cret"
+            }]})
+            .to_string(),
+        )
+        .unwrap();
+        let config = ProcessingConfig {
+            devices: Some(ReloadableDeviceRegistry::load(path.to_str().unwrap()).unwrap()),
+            signature_policy: SignaturePolicy::new(SignatureMode::Strict, TimeDelta::minutes(5)),
+            ..ProcessingConfig::default()
+        };
+        std::fs::remove_file(&path).unwrap();
 
-        debug!(target: "app", "result = {}", result);
-        // for bad JSON payloads, get_msg_byte returns an empty Vec<u8>
-        assert_eq!(msg_byte_arr.len(), 0);
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
+        let payload = FeaturePayload {
+            value: FeatureValue::Float(21.5),
+        };
+        let content = signed_content(device_uuid, feature_uuid, "nonce-1", received_at(), &payload);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"device-s3cret").unwrap();
+        mac.update(content.as_bytes());
+        let signed = json!({
+            "deviceUuid": device_uuid,
+            "featureUuid": feature_uuid,
+            "apiToken": "473a4861-632b-4915-b01e-cf1d418966c6",
+            "payload": {"value": 21.5},
+            "timestamp": RECEIVED_AT,
+            "nonce": "nonce-1",
+            "signature": hex::encode(mac.finalize().into_bytes())
+        })
+        .to_string();
+        let msg_byte_arr = get_msg_byte(&topic, signed.as_str(), received_at(), &config).unwrap();
+        let message: serde_json::Value = serde_json::from_slice(&msg_byte_arr).unwrap();
+        assert_eq!(message["signatureVerified"], true);
+
+        // the same message again
+        let res = get_msg_byte(&topic, signed.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "temperature".to_string(),
+                reason: RejectReason::ReplayedNonce("nonce-1".to_string()),
+            }
+            .to_string()
+        );
+
+        // unsigned readings are dropped in strict mode
+        let unsigned = get_expected_json_string::<f64>(device_uuid, feature_uuid, 21.5, &topic);
+        let res = get_msg_byte(&topic, unsigned.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "temperature".to_string(),
+                reason: RejectReason::Unsigned(device_uuid.to_string()),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_byte_mapped_values() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        let topic: Topic = Topic::new(format!("sensors/{}/motion", device_uuid).as_str());
+        let expected_value = get_expected_json_string::<i64>(device_uuid, feature_uuid, 1, &topic);
+
+        // Tasmota and Shelly values are mapped by the built-in registry in strict mode too
+        for value in [json!("ON"), json!("detected"), json!(true)] {
+            let payload = get_expected_json_string(device_uuid, feature_uuid, value, &topic);
+            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, payload.as_str(), received_at(), &config).unwrap();
+            assert_eq!(from_utf8(msg_byte_arr.as_slice()).unwrap(), expected_value);
+        }
+
+        // unmapped strings are still rejected
+        let payload = get_expected_json_string(device_uuid, feature_uuid, "maybe", &topic);
+        let res = get_msg_byte(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "motion".to_string(),
+                reason: RejectReason::TypeMismatch(ValueType::Int),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_byte_lenient_coercion() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig {
+            coercion: CoercionMode::Lenient,
+            ..ProcessingConfig::default()
+        };
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+
+        // integral float for an int feature, rejected in strict mode
+        let topic: Topic = Topic::new(format!("sensors/{}/motion", device_uuid).as_str());
+        let payload = get_expected_json_string::<f64>(device_uuid, feature_uuid, 1.0, &topic);
+        let expected_value = get_expected_json_string::<i64>(device_uuid, feature_uuid, 1, &topic);
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, payload.as_str(), received_at(), &config).unwrap();
+        assert_eq!(from_utf8(msg_byte_arr.as_slice()).unwrap(), expected_value);
+
+        // numeric string for a float feature
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
+        let payload = get_expected_json_string(device_uuid, feature_uuid, "21.5", &topic);
+        let expected_value = get_expected_json_string::<f64>(device_uuid, feature_uuid, 21.5, &topic);
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, payload.as_str(), received_at(), &config).unwrap();
+        assert_eq!(from_utf8(msg_byte_arr.as_slice()).unwrap(), expected_value);
+
+        // values that aren't numbers are still rejected
+        let payload = get_expected_json_string(device_uuid, feature_uuid, "warm", &topic);
+        let res = get_msg_byte(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            MessageError::Rejected {
+                feature: "temperature".to_string(),
+                reason: RejectReason::TypeMismatch(ValueType::Float),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_byte_registry_feature() {
+        // init logger and env
+        let _ = init();
+        let mut config = ProcessingConfig::default();
+        let mut radon = FeatureSpec::new("radon", ValueType::Float, Some("Bq/m³"));
+        radon.max = Some(10000.0);
+        config.registry.insert(radon);
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        let topic: Topic = Topic::new(format!("sensors/{}/radon", device_uuid).as_str());
+
+        let expected_value = get_expected_json_string::<f64>(device_uuid, feature_uuid, 415.5, &topic);
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, expected_value.as_str(), received_at(), &config).unwrap();
+        assert_eq!(from_utf8(msg_byte_arr.as_slice()).unwrap(), expected_value);
+
+        // value out of the allowed range
+        let out_of_range = get_expected_json_string::<f64>(device_uuid, feature_uuid, 20000.0, &topic);
+        let res = get_msg_byte(&topic, out_of_range.as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "Rejected radon reading error: value 20000 is greater than max 10000"
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_byte_timestamp() {
+        // init logger and env
+        let _ = init();
+        let mut config = ProcessingConfig::default();
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
+        let mut payload = serde_json::from_str::<serde_json::Value>(
+            get_expected_json_string::<f64>(device_uuid, feature_uuid, 12.0, &topic).as_str(),
+        )
+        .unwrap();
+
+        // device timestamp in epoch milliseconds, forwarded as RFC 3339
+        payload["timestamp"] = json!(1766658540000_i64);
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, payload.to_string().as_str(), received_at(), &config).unwrap();
+        let result: serde_json::Value = serde_json::from_slice(msg_byte_arr.as_slice()).unwrap();
+        assert_eq!(result["timestamp"], "2025-12-25T10:29:00Z");
+        assert_eq!(result["receivedAt"], RECEIVED_AT);
+
+        // device timestamp too far in the future
+        payload["timestamp"] = json!("2025-12-25T11:30:00Z");
+        config.timestamp_policy.action = SkewAction::Reject;
+        let res = get_msg_byte(&topic, payload.to_string().as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "Rejected temperature reading error: timestamp is 3600 seconds in the future"
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_byte_unit_conversion() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        let topic: Topic = Topic::new(format!("sensors/{}/airpressure", device_uuid).as_str());
+        let mut payload = serde_json::from_str::<serde_json::Value>(
+            get_expected_json_string::<i64>(device_uuid, feature_uuid, 101325, &topic).as_str(),
+        )
+        .unwrap();
+
+        // pressure in Pa, converted to hPa
+        payload["unit"] = json!("Pa");
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, payload.to_string().as_str(), received_at(), &config).unwrap();
+        let result: serde_json::Value = serde_json::from_slice(msg_byte_arr.as_slice()).unwrap();
+        assert_eq!(result["payload"]["value"], 1013.25);
+        assert_eq!(result["unit"], "hPa");
+        assert_eq!(result["originalUnit"], "Pa");
+
+        // unknown unit
+        payload["unit"] = json!("atm");
+        let res = get_msg_byte(&topic, payload.to_string().as_str(), received_at(), &config);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "Rejected airpressure reading error: unit atm cannot be converted"
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_bytes_multi() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let api_token = "473a4861-632b-4915-b01e-cf1d418966c6";
+        let topic: Topic = Topic::new(format!("sensors/{}/multi", device_uuid).as_str());
+        let payload = json!({
+            "deviceUuid": device_uuid,
+            "apiToken": api_token,
+            "timestamp": RECEIVED_AT,
+            "readings": [
+                {"featureName": "temperature", "featureUuid": "f1", "payload": {"value": 21.5}},
+                {"featureName": "humidity", "featureUuid": "f2", "payload": {"value": 40.0}},
+                {"featureName": "airpressure", "featureUuid": "f3", "payload": {"value": 101325}, "unit": "Pa"},
+                {"featureName": "humidity", "featureUuid": "f4", "payload": {"value": 140.0}},
+                {"featureName": "unknown", "featureUuid": "f5", "payload": {"value": 1}},
+            ]
+        });
+
+        let results = get_msg_bytes(&topic, payload.to_string().as_str(), received_at(), &config);
+        assert_eq!(results.len(), 5);
+        let messages: Vec<serde_json::Value> = results[..3]
+            .iter()
+            .map(|res| serde_json::from_slice(res.as_ref().unwrap()).unwrap())
+            .collect();
+        for (message, (feature_name, feature_uuid)) in
+            messages
+                .iter()
+                .zip([("temperature", "f1"), ("humidity", "f2"), ("airpressure", "f3")])
+        {
+            assert_eq!(message["topic"]["featureName"], feature_name);
+            assert_eq!(message["topic"]["deviceId"], device_uuid);
+            assert_eq!(message["featureUuid"], feature_uuid);
+            assert_eq!(message["deviceUuid"], device_uuid);
+            assert_eq!(message["apiToken"], api_token);
+            assert_eq!(message["timestamp"], RECEIVED_AT);
+        }
+        assert_eq!(messages[2]["payload"]["value"], 1013.25);
+        assert_eq!(
+            results[3].as_ref().err().unwrap().to_string(),
+            "Rejected humidity reading error: value 140 is greater than max 100"
+        );
+        assert_eq!(
+            results[4].as_ref().err().unwrap().to_string(),
+            MessageError::UnknownFeature(String::from("unknown")).to_string()
+        );
+    }
+
+    #[test]
+    fn wrong_get_msg_bytes_multi() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let topic: Topic = Topic::new("sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/multi");
+        for (payload, expected_err) in [
+            (
+                r#"{"deviceUuid": "d1", "apiToken": "t1", "readings": []}"#,
+                MessageError::EmptyMessageError,
+            ),
+            (
+                r#"{"deviceUuid": "d1", "apiToken": "t1"}"#,
+                MessageError::MissingField(String::from("readings")),
+            ),
+            (
+                r#"{"deviceUuid": "d1", "apiToken": "t1", "readings": [{"featureName": "temperature"}]}"#,
+                MessageError::MissingField(String::from("featureUuid")),
+            ),
+        ] {
+            let results = get_msg_bytes(&topic, payload, received_at(), &config);
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].as_ref().err().unwrap().to_string(), expected_err.to_string());
+        }
+    }
+
+    fn get_batch_payload(values: serde_json::Value) -> String {
+        json!({
+            "deviceUuid": "246e3256-f0dd-4fcb-82c5-ee20c2267eeb",
+            "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896",
+            "apiToken": "473a4861-632b-4915-b01e-cf1d418966c6",
+            "values": values,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn ok_get_msg_bytes_batch() {
+        // init logger and env
+        let _ = init();
+        let mut config = ProcessingConfig::default();
+
+        let topic: Topic = Topic::new("sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature");
+        let payload = get_batch_payload(json!([
+            {"value": 21.0, "timestamp": "2025-12-25T10:20:00Z"},
+            {"value": 20.0, "timestamp": "2025-12-25T10:10:00Z"},
+            {"value": 19.0, "timestamp": "2025-12-01T10:00:00Z"},
+            {"value": 22.0, "timestamp": "2025-12-25T10:30:00Z"},
+        ]));
+
+        // expanded in messages ordered by timestamp, the oldest value is rejected
+        let results = get_msg_bytes(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(results.len(), 4);
+        assert_eq!(
+            results[0].as_ref().err().unwrap().to_string(),
+            "Rejected temperature reading error: timestamp is 2075400 seconds older than allowed"
+        );
+        let messages: Vec<serde_json::Value> = results[1..]
+            .iter()
+            .map(|res| serde_json::from_slice(res.as_ref().unwrap()).unwrap())
+            .collect();
+        assert_eq!(messages[0]["payload"]["value"], 20.0);
+        assert_eq!(messages[0]["timestamp"], "2025-12-25T10:10:00Z");
+        assert_eq!(messages[1]["payload"]["value"], 21.0);
+        assert_eq!(messages[2]["payload"]["value"], 22.0);
+        assert_eq!(messages[2]["receivedAt"], RECEIVED_AT);
+
+        // single envelope with valid values, followed by rejected ones
+        config.batch_policy.mode = BatchMode::Envelope;
+        let results = get_msg_bytes(&topic, payload.as_str(), received_at(), &config);
+        assert_eq!(results.len(), 2);
+        let envelope: serde_json::Value = serde_json::from_slice(results[0].as_ref().unwrap()).unwrap();
+        assert_eq!(envelope["topic"]["featureName"], "temperature");
+        assert_eq!(
+            envelope["values"],
+            json!([
+                {"value": 20.0, "timestamp": "2025-12-25T10:10:00Z"},
+                {"value": 21.0, "timestamp": "2025-12-25T10:20:00Z"},
+                {"value": 22.0, "timestamp": "2025-12-25T10:30:00Z"},
+            ])
+        );
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn wrong_get_msg_bytes_batch() {
+        // init logger and env
+        let _ = init();
+        let mut config = ProcessingConfig::default();
+        config.batch_policy.max_size = 2;
+
+        let topic: Topic = Topic::new("sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature");
+        let values = json!([
+            {"value": 21.0, "timestamp": RECEIVED_AT},
+            {"value": 20.0, "timestamp": RECEIVED_AT},
+            {"value": 19.0, "timestamp": RECEIVED_AT},
+        ]);
+        let results = get_msg_bytes(&topic, get_batch_payload(values).as_str(), received_at(), &config);
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].as_ref().err().unwrap().to_string(),
+            "Rejected temperature reading error: batch of 3 values exceeds max size 2"
+        );
+
+        // empty batch or values without timestamp
+        for (values, expected_err) in [
+            (json!([]), MessageError::EmptyMessageError),
+            (
+                json!([{"value": 21.0}]),
+                MessageError::MissingField(String::from("timestamp")),
+            ),
+        ] {
+            let results = get_msg_bytes(&topic, get_batch_payload(values).as_str(), received_at(), &config);
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].as_ref().err().unwrap().to_string(), expected_err.to_string());
+        }
+    }
+
+    #[test]
+    fn ok_get_msg_bytes_binary_formats() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
+        let json_payload = get_expected_json_string::<f64>(device_uuid, feature_uuid, 21.5, &topic);
+        let notification: serde_json::Value = serde_json::from_str(json_payload.as_str()).unwrap();
+        let expected = get_msg_byte(&topic, json_payload.as_str(), received_at(), &config).unwrap();
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&notification, &mut cbor).unwrap();
+        let msgpack = rmp_serde::to_vec_named(&notification).unwrap();
+        for (payload, format) in [(cbor, PayloadFormat::Cbor), (msgpack, PayloadFormat::MessagePack)] {
+            let results = get_msg_bytes_with_format(&topic, &payload, format, received_at(), &config);
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].as_ref().unwrap(), &expected);
+        }
+    }
+
+    #[test]
+    fn wrong_get_msg_bytes_binary_formats() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let topic: Topic = Topic::new("sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature");
+        // truncated map with a single key
+        let results = get_msg_bytes_with_format(&topic, &[0xa1, 0x61], PayloadFormat::Cbor, received_at(), &config);
+        assert_eq!(results.len(), 1);
+        assert!(
+            results[0]
+                .as_ref()
+                .err()
+                .unwrap()
+                .to_string()
+                .starts_with("Cannot decode message error: invalid CBOR payload: ")
+        );
+
+        // valid MessagePack, but not a Notification
+        let payload = rmp_serde::to_vec_named(&json!({"value": 1})).unwrap();
+        let results = get_msg_bytes_with_format(&topic, &payload, PayloadFormat::MessagePack, received_at(), &config);
+        assert_eq!(
+            results[0].as_ref().err().unwrap().to_string(),
+            MessageError::MissingField(String::from("deviceUuid")).to_string()
+        );
+    }
+
+    #[test]
+    fn ok_get_msg_byte_schema_version_v1() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig {
+            schema_version: SchemaVersion::V1,
+            ..ProcessingConfig::default()
+        };
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let feature_uuid = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+        let topic: Topic = Topic::new(format!("sensors/{}/temperature", device_uuid).as_str());
+        let mut payload = serde_json::from_str::<serde_json::Value>(
+            get_expected_json_string::<f64>(device_uuid, feature_uuid, 12.0, &topic).as_str(),
+        )
+        .unwrap();
+        payload["timestamp"] = json!(RECEIVED_AT);
+
+        // previous layout, without schemaVersion and fields added in version 2
+        let expected_value = json!({
+            "apiToken": payload["apiToken"],
+            "deviceUuid": device_uuid,
+            "featureUuid": feature_uuid,
+            "topic": payload["topic"],
+            "payload": {"value": 12.0}
+        });
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, payload.to_string().as_str(), received_at(), &config).unwrap();
+        assert_eq!(from_utf8(msg_byte_arr.as_slice()).unwrap(), expected_value.to_string());
     }
 }
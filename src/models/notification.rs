@@ -1,12 +1,129 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::models::payload_trait::PayloadTrait;
+use crate::models::payload_trait::{FeaturePayload, FeatureValue, PayloadTrait};
+use crate::models::timestamp::{deserialize_required_timestamp, deserialize_timestamp};
 
+// string fields borrow from the MQTT payload buffer when they don't contain escape sequences,
+// so they are copied only once, into the outgoing Message
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct Notification<T: PayloadTrait> {
-    pub device_uuid: String,
-    pub feature_uuid: String,
-    pub api_token: String,
+pub struct Notification<'a, T: PayloadTrait> {
+    #[serde(borrow)]
+    pub device_uuid: Cow<'a, str>,
+    #[serde(borrow)]
+    pub feature_uuid: Cow<'a, str>,
+    #[serde(borrow)]
+    pub api_token: Cow<'a, str>,
     pub payload: T,
+    // optional unit of the value, converted by the producer to the canonical unit of the feature
+    #[serde(default)]
+    pub unit: Option<String>,
+    // optional time of the reading, provided by the device
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    pub timestamp: Option<DateTime<Utc>>,
+    // optional hex HMAC-SHA256 of the reading, see `SignaturePolicy`
+    #[serde(default)]
+    pub signature: Option<String>,
+    // unique value of signed readings, to reject replayed messages
+    #[serde(default)]
+    pub nonce: Option<String>,
+}
+
+// payload of composite topics, with readings of several features of the same device
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiNotification<'a> {
+    #[serde(borrow)]
+    pub device_uuid: Cow<'a, str>,
+    #[serde(borrow)]
+    pub api_token: Cow<'a, str>,
+    #[serde(borrow)]
+    pub readings: Vec<Reading<'a>>,
+    // time of all readings, provided by the device
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Reading<'a> {
+    #[serde(borrow)]
+    pub feature_name: Cow<'a, str>,
+    #[serde(borrow)]
+    pub feature_uuid: Cow<'a, str>,
+    pub payload: FeaturePayload,
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+impl<'a> MultiNotification<'a> {
+    // splits this notification in one Notification for each reading, with its feature name.
+    // Cloning borrowed fields doesn't allocate.
+    pub fn into_notifications(self) -> impl Iterator<Item = (Cow<'a, str>, Notification<'a, FeaturePayload>)> {
+        let device_uuid = self.device_uuid;
+        let api_token = self.api_token;
+        let timestamp = self.timestamp;
+        self.readings.into_iter().map(move |reading| {
+            let notification = Notification {
+                device_uuid: device_uuid.clone(),
+                feature_uuid: reading.feature_uuid,
+                api_token: api_token.clone(),
+                payload: reading.payload,
+                unit: reading.unit,
+                timestamp,
+                signature: None,
+                nonce: None,
+            };
+            (reading.feature_name, notification)
+        })
+    }
+}
+
+// payload with readings of a single feature, buffered by the device while offline
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchNotification<'a> {
+    #[serde(borrow)]
+    pub device_uuid: Cow<'a, str>,
+    #[serde(borrow)]
+    pub feature_uuid: Cow<'a, str>,
+    #[serde(borrow)]
+    pub api_token: Cow<'a, str>,
+    pub values: Vec<TimedValue>,
+    // unit of all values
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimedValue {
+    pub value: FeatureValue,
+    #[serde(deserialize_with = "deserialize_required_timestamp")]
+    pub timestamp: DateTime<Utc>,
+}
+
+impl<'a> BatchNotification<'a> {
+    // splits this notification in one Notification for each value, ordered by timestamp
+    pub fn into_notifications(mut self) -> impl Iterator<Item = Notification<'a, FeaturePayload>> {
+        self.values.sort_by_key(|timed_value| timed_value.timestamp);
+        let device_uuid = self.device_uuid;
+        let feature_uuid = self.feature_uuid;
+        let api_token = self.api_token;
+        let unit = self.unit;
+        self.values.into_iter().map(move |timed_value| Notification {
+            device_uuid: device_uuid.clone(),
+            feature_uuid: feature_uuid.clone(),
+            api_token: api_token.clone(),
+            payload: FeaturePayload {
+                value: timed_value.value,
+            },
+            unit: unit.clone(),
+            timestamp: Some(timed_value.timestamp),
+            signature: None,
+            nonce: None,
+        })
+    }
 }
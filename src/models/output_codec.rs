@@ -0,0 +1,187 @@
+use prost::Message as ProtoMessage;
+use serde::{Deserialize, Serialize};
+
+use crate::models::message::{BatchMessage, Message};
+use crate::models::payload_trait::FeaturePayload;
+use crate::models::proto;
+use crate::models::schema_version::{MessageV1, SchemaVersion};
+
+// encoding of the messages published via AMQP
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputCodec {
+    #[default]
+    Json,
+    Cbor,
+    Msgpack,
+    // schema defined in proto/message.proto
+    Protobuf,
+}
+
+impl OutputCodec {
+    // AMQP content_type of the encoded messages
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputCodec::Json => "application/json",
+            OutputCodec::Cbor => "application/cbor",
+            OutputCodec::Msgpack => "application/msgpack",
+            OutputCodec::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    pub fn encode_message(&self, message: &Message<FeaturePayload>, version: SchemaVersion) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode_message_into(message, version, &mut bytes);
+        bytes
+    }
+
+    // appends the encoded message to `out`, so the caller can reuse the same buffer for every message
+    pub fn encode_message_into(&self, message: &Message<FeaturePayload>, version: SchemaVersion, out: &mut Vec<u8>) {
+        match (self, version) {
+            (OutputCodec::Protobuf, _) => proto::Message::with_version(message, version).encode(out).unwrap(),
+            (_, SchemaVersion::V1) => self.encode_serde_into(&MessageV1::from(message), out),
+            (_, SchemaVersion::V2) => self.encode_serde_into(message, out),
+        }
+    }
+
+    pub fn encode_batch(&self, message: &BatchMessage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode_batch_into(message, &mut bytes);
+        bytes
+    }
+
+    pub fn encode_batch_into(&self, message: &BatchMessage, out: &mut Vec<u8>) {
+        match self {
+            OutputCodec::Protobuf => proto::BatchMessage::from(message).encode(out).unwrap(),
+            _ => self.encode_serde_into(message, out),
+        }
+    }
+
+    // writing into a Vec can't fail, only serialization of invalid values could
+    fn encode_serde_into<T: Serialize>(&self, value: &T, out: &mut Vec<u8>) {
+        match self {
+            OutputCodec::Cbor => ciborium::into_writer(value, out).unwrap(),
+            // with field names, like JSON, instead of arrays
+            OutputCodec::Msgpack => rmp_serde::encode::write_named(out, value).unwrap(),
+            _ => serde_json::to_writer(out, value).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::message::{Envelope, Message};
+    use crate::models::output_codec::OutputCodec;
+    use crate::models::payload_trait::{FeaturePayload, FeatureValue};
+    use crate::models::proto;
+    use crate::models::schema_version::SchemaVersion;
+    use crate::models::token_policy::ApiTokenPolicy;
+    use crate::models::topic::Topic;
+    use chrono::{DateTime, Utc};
+    use pretty_assertions::assert_eq;
+    use prost::Message as ProtoMessage;
+    use serde_json::Value;
+
+    fn get_message() -> Message<FeaturePayload> {
+        let received_at: DateTime<Utc> = "2025-12-25T10:30:00Z".parse().unwrap();
+        Message::new(
+            String::from("473a4861-632b-4915-b01e-cf1d418966c6"),
+            String::from("246e3256-f0dd-4fcb-82c5-ee20c2267eeb"),
+            String::from("41cb3f47-894c-45e9-90d9-a4d4de903896"),
+            Topic::new("sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature"),
+            FeaturePayload {
+                value: FeatureValue::Float(21.5),
+            },
+            Some(received_at),
+            received_at,
+        )
+        .with_units(Some(String::from("°C")), Some(String::from("°F")))
+    }
+
+    #[test]
+    fn ok_encode_message() {
+        let message = get_message();
+        let json: Value =
+            serde_json::from_slice(&OutputCodec::Json.encode_message(&message, SchemaVersion::LATEST)).unwrap();
+
+        // self-describing formats contain the same document as JSON
+        let cbor: Value = ciborium::from_reader(
+            OutputCodec::Cbor
+                .encode_message(&message, SchemaVersion::LATEST)
+                .as_slice(),
+        )
+        .unwrap();
+        assert_eq!(cbor, json);
+        let msgpack: Value =
+            rmp_serde::from_slice(&OutputCodec::Msgpack.encode_message(&message, SchemaVersion::LATEST)).unwrap();
+        assert_eq!(msgpack, json);
+
+        let decoded = proto::Message::decode(
+            OutputCodec::Protobuf
+                .encode_message(&message, SchemaVersion::LATEST)
+                .as_slice(),
+        )
+        .unwrap();
+        assert_eq!(decoded.device_uuid, message.device_uuid);
+        assert_eq!(decoded.topic.unwrap().feature_name, "temperature");
+        assert_eq!(
+            decoded.payload.unwrap().value.unwrap().kind,
+            Some(proto::Kind::FloatValue(21.5))
+        );
+        assert_eq!(decoded.unit.as_deref(), Some("°C"));
+        assert_eq!(decoded.original_unit.as_deref(), Some("°F"));
+        assert_eq!(decoded.timestamp_ms, Some(1766658600000));
+        assert_eq!(decoded.received_at_ms, 1766658600000);
+        assert_eq!(decoded.schema_version, 2);
+    }
+
+    #[test]
+    fn ok_encode_message_v1() {
+        let message = get_message();
+        let json: Value =
+            serde_json::from_slice(&OutputCodec::Json.encode_message(&message, SchemaVersion::V1)).unwrap();
+        assert_eq!(
+            json.as_object().unwrap().keys().collect::<Vec<&String>>(),
+            vec!["apiToken", "deviceUuid", "featureUuid", "topic", "payload"]
+        );
+
+        let bytes = OutputCodec::Protobuf.encode_message(&message, SchemaVersion::V1);
+        let decoded = proto::Message::decode(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.schema_version, 1);
+        assert_eq!(decoded.unit, None);
+        assert_eq!(decoded.timestamp_ms, None);
+        assert_eq!(decoded.received_at_ms, 0);
+    }
+
+    #[test]
+    fn ok_encode_message_api_token_policy() {
+        let mut envelope = Envelope::Message(get_message());
+        let api_token = envelope.take_api_token();
+        let hash_key = b"hash-key";
+
+        let header = envelope.forward_api_token(&api_token, ApiTokenPolicy::Header, hash_key);
+        assert_eq!(header, Some(api_token.clone()));
+        for version in [SchemaVersion::V1, SchemaVersion::LATEST] {
+            let json: Value = serde_json::from_slice(&envelope.encode(OutputCodec::Json, version)).unwrap();
+            assert_eq!(json.get("apiToken"), None);
+        }
+
+        let header = envelope.forward_api_token(&api_token, ApiTokenPolicy::Hash, hash_key);
+        assert_eq!(header, None);
+        let json: Value = serde_json::from_slice(&envelope.encode(OutputCodec::Json, SchemaVersion::LATEST)).unwrap();
+        assert_eq!(
+            json["apiToken"],
+            "fc33a674e2a21827e69abdf0c20f4239937c9587b19475ea328400c353e20d18"
+        );
+
+        envelope.forward_api_token(&api_token, ApiTokenPolicy::Forward, hash_key);
+        let json: Value = serde_json::from_slice(&envelope.encode(OutputCodec::Json, SchemaVersion::LATEST)).unwrap();
+        assert_eq!(json["apiToken"], api_token);
+    }
+
+    #[test]
+    fn check_content_type() {
+        assert_eq!(OutputCodec::default().content_type(), "application/json");
+        assert_eq!(OutputCodec::Protobuf.content_type(), "application/x-protobuf");
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::errors::decode_error::DecodeError;
+
+// encoding of the payload of an MQTT message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl PayloadFormat {
+    // optional last topic level that selects the format, like `sensors/<id>/temperature/cbor`
+    pub const TOPIC_SUFFIXES: [&'static str; 3] = ["json", "cbor", "msgpack"];
+
+    pub fn from_topic_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "json" => Some(PayloadFormat::Json),
+            "cbor" => Some(PayloadFormat::Cbor),
+            "msgpack" => Some(PayloadFormat::MessagePack),
+            _ => None,
+        }
+    }
+
+    // MQTT v5 content-type property
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        // ignore parameters, like `; charset=utf-8`
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime.to_ascii_lowercase().as_str() {
+            "application/json" | "text/json" => Some(PayloadFormat::Json),
+            "application/cbor" => Some(PayloadFormat::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(PayloadFormat::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    // guess the format from the first byte, because all payloads are maps:
+    // `{` in JSON, major type 5 in CBOR and fixmap, map16 or map32 in MessagePack
+    pub fn from_magic_byte(payload: &[u8]) -> Self {
+        match payload.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(0xa0..=0xbf) => PayloadFormat::Cbor,
+            Some(0x80..=0x8f | 0xde | 0xdf) => PayloadFormat::MessagePack,
+            _ => PayloadFormat::Json,
+        }
+    }
+
+    // removes the format suffix from the topic, if any
+    pub fn split_topic(topic: &str) -> (&str, Option<Self>) {
+        if let Some((base, suffix)) = topic.rsplit_once('/')
+            && let Some(format) = PayloadFormat::from_topic_suffix(suffix)
+        {
+            return (base, Some(format));
+        }
+        (topic, None)
+    }
+
+    // JSON and MessagePack strings are borrowed from the payload when possible.
+    // ciborium can't borrow, so CBOR is decoded to a generic value first and then copied into T.
+    pub fn decode<'de, T: Deserialize<'de>>(&self, payload: &'de [u8]) -> Result<T, DecodeError> {
+        match self {
+            PayloadFormat::Json => Ok(serde_json::from_slice(payload)?),
+            PayloadFormat::Cbor => {
+                let value: ciborium::Value = ciborium::from_reader(payload)?;
+                value.deserialized().map_err(|ciborium::value::Error::Custom(msg)| {
+                    DecodeError::Cbor(ciborium::de::Error::Semantic(None, msg))
+                })
+            }
+            PayloadFormat::MessagePack => Ok(rmp_serde::from_slice(payload)?),
+        }
+    }
+}
+
+impl fmt::Display for PayloadFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayloadFormat::Json => fmt.write_str("JSON"),
+            PayloadFormat::Cbor => fmt.write_str("CBOR"),
+            PayloadFormat::MessagePack => fmt.write_str("MessagePack"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::notification::Notification;
+    use crate::models::payload_format::PayloadFormat;
+    use crate::models::payload_trait::FeaturePayload;
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+    use std::borrow::Cow;
+
+    #[test]
+    fn check_detect_payload_format() {
+        assert_eq!(
+            PayloadFormat::split_topic("sensors/abc/temperature/cbor"),
+            ("sensors/abc/temperature", Some(PayloadFormat::Cbor))
+        );
+        assert_eq!(
+            PayloadFormat::split_topic("sensors/abc/temperature"),
+            ("sensors/abc/temperature", None)
+        );
+        assert_eq!(
+            PayloadFormat::from_content_type("application/msgpack"),
+            Some(PayloadFormat::MessagePack)
+        );
+        assert_eq!(
+            PayloadFormat::from_content_type("application/json; charset=utf-8"),
+            Some(PayloadFormat::Json)
+        );
+        assert_eq!(PayloadFormat::from_content_type("text/plain"), None);
+
+        let value = json!({"payload": {"value": 12.5}});
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&value, &mut cbor).unwrap();
+        let msgpack = rmp_serde::to_vec_named(&value).unwrap();
+        assert_eq!(
+            PayloadFormat::from_magic_byte(value.to_string().as_bytes()),
+            PayloadFormat::Json
+        );
+        assert_eq!(PayloadFormat::from_magic_byte(&cbor), PayloadFormat::Cbor);
+        assert_eq!(PayloadFormat::from_magic_byte(&msgpack), PayloadFormat::MessagePack);
+        assert_eq!(PayloadFormat::Cbor.decode::<Value>(&cbor).unwrap(), value);
+        assert_eq!(PayloadFormat::MessagePack.decode::<Value>(&msgpack).unwrap(), value);
+    }
+
+    #[test]
+    fn wrong_decode_payload_format() {
+        let err = PayloadFormat::Cbor.decode::<Value>(&[0xa1, 0x61]).err().unwrap();
+        assert!(err.to_string().starts_with("invalid CBOR payload: "));
+        let err = PayloadFormat::MessagePack.decode::<Value>(&[0x81, 0xa1]).err().unwrap();
+        assert!(err.to_string().starts_with("invalid MessagePack payload: "));
+    }
+
+    #[test]
+    fn check_decode_borrows_payload() {
+        let value = json!({
+            "deviceUuid": "246e3256-f0dd-4fcb-82c5-ee20c2267eeb",
+            "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896",
+            "apiToken": "token\"with\"quotes",
+            "payload": {"value": 21.5}
+        });
+        let json = serde_json::to_vec(&value).unwrap();
+        let msgpack = rmp_serde::to_vec_named(&value).unwrap();
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&value, &mut cbor).unwrap();
+
+        let decoded: Notification<FeaturePayload> = PayloadFormat::Json.decode(&json).unwrap();
+        assert!(matches!(decoded.device_uuid, Cow::Borrowed(_)));
+        // escaped strings can't be borrowed
+        assert!(matches!(decoded.api_token, Cow::Owned(_)));
+        assert_eq!(decoded.api_token, "token\"with\"quotes");
+
+        let decoded: Notification<FeaturePayload> = PayloadFormat::MessagePack.decode(&msgpack).unwrap();
+        assert!(matches!(decoded.device_uuid, Cow::Borrowed(_)));
+        assert!(matches!(decoded.api_token, Cow::Borrowed(_)));
+
+        let decoded: Notification<FeaturePayload> = PayloadFormat::Cbor.decode(&cbor).unwrap();
+        assert!(matches!(decoded.device_uuid, Cow::Owned(_)));
+        assert_eq!(decoded.device_uuid, "246e3256-f0dd-4fcb-82c5-ee20c2267eeb");
+    }
+}
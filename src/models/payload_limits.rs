@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::errors::limit_error::LimitError;
+use crate::models::payload_format::PayloadFormat;
+
+// object keys of supported payloads, by object: single readings, batches and multi readings at the root,
+// then `payload`, an element of `values` in batches and an element of `readings` in multi readings
+const ROOT_FIELDS: [&str; 10] = [
+    "deviceUuid",
+    "featureUuid",
+    "apiToken",
+    "payload",
+    "values",
+    "readings",
+    "unit",
+    "timestamp",
+    "signature",
+    "nonce",
+];
+const PAYLOAD_FIELDS: [&str; 1] = ["value"];
+const TIMED_VALUE_FIELDS: [&str; 2] = ["value", "timestamp"];
+const READING_FIELDS: [&str; 4] = ["featureName", "featureUuid", "payload", "unit"];
+
+// object or array of a payload, to know the fields allowed in it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Container {
+    Root,
+    Payload,
+    TimedValues,
+    TimedValue,
+    Readings,
+    Reading,
+    // containers that are not part of any payload, like an object as value
+    Other,
+}
+
+// container open while scanning JSON, with the range of the last key read, if it's an object
+struct OpenContainer {
+    container: Container,
+    is_object: bool,
+    key: Option<(usize, usize)>,
+}
+
+impl Container {
+    // allowed keys of objects
+    fn fields(&self) -> &'static [&'static str] {
+        match self {
+            Container::Root => &ROOT_FIELDS,
+            Container::Payload => &PAYLOAD_FIELDS,
+            Container::TimedValue => &TIMED_VALUE_FIELDS,
+            Container::Reading => &READING_FIELDS,
+            _ => &[],
+        }
+    }
+
+    // container opened as the value of `key` in this object, or as an element of this array
+    fn child(&self, key: Option<&[u8]>) -> Container {
+        match (self, key) {
+            (Container::Root, Some(b"payload")) | (Container::Reading, Some(b"payload")) => Container::Payload,
+            (Container::Root, Some(b"values")) => Container::TimedValues,
+            (Container::Root, Some(b"readings")) => Container::Readings,
+            (Container::TimedValues, None) => Container::TimedValue,
+            (Container::Readings, None) => Container::Reading,
+            _ => Container::Other,
+        }
+    }
+}
+
+// what to do with object keys that are not part of the payload object they are in
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownFieldsAction {
+    // ignore them, like serde does
+    #[default]
+    Allow,
+    Reject,
+}
+
+// limits checked before decoding, so oversize or overly complex JSON payloads are not fully parsed.
+// Binary payloads within max_bytes are decoded to a generic value to check the others.
+// A limit of 0 disables the check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadLimits {
+    pub max_bytes: usize,
+    // nesting of objects and arrays, valid payloads have depth 4 at most
+    pub max_depth: usize,
+    // length of strings, keys included, before unescaping in JSON
+    pub max_string_len: usize,
+    pub unknown_fields: UnknownFieldsAction,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 65536,
+            max_depth: 16,
+            max_string_len: 1024,
+            unknown_fields: UnknownFieldsAction::Allow,
+        }
+    }
+}
+
+impl PayloadLimits {
+    // checks the size and the structure of payloads: depth, strings and, if rejected, unknown fields.
+    // CBOR and MessagePack are binary, so their structure is checked on the decoded value.
+    pub fn check(&self, payload: &[u8], format: PayloadFormat) -> Result<(), LimitError> {
+        if self.max_bytes > 0 && payload.len() > self.max_bytes {
+            return Err(LimitError::PayloadTooLarge(payload.len(), self.max_bytes));
+        }
+        match format {
+            PayloadFormat::Json => self.check_json(payload),
+            // invalid payloads are reported by the decoder afterwards
+            _ => match format.decode::<Value>(payload) {
+                Ok(value) => self.check_value(&value, Container::Root, 1),
+                Err(_) => Ok(()),
+            },
+        }
+    }
+
+    // single pass over the bytes, without allocations, tracking only nesting, keys and strings.
+    // Syntax errors are ignored, because they are reported by the parser afterwards.
+    fn check_json(&self, payload: &[u8]) -> Result<(), LimitError> {
+        let mut containers: Vec<OpenContainer> = Vec::new();
+        let mut expect_key = false;
+        let mut i = 0;
+        while i < payload.len() {
+            match payload[i] {
+                byte @ (b'{' | b'[') => {
+                    if self.max_depth > 0 && containers.len() >= self.max_depth {
+                        return Err(LimitError::TooDeep(self.max_depth));
+                    }
+                    let is_object = byte == b'{';
+                    let container = match containers.last() {
+                        // the root of valid payloads is an object
+                        None if is_object => Container::Root,
+                        None => Container::Other,
+                        Some(parent) if parent.is_object => parent
+                            .container
+                            .child(parent.key.map(|(start, end)| &payload[start..end])),
+                        Some(parent) => parent.container.child(None),
+                    };
+                    containers.push(OpenContainer {
+                        container,
+                        is_object,
+                        key: None,
+                    });
+                    expect_key = is_object;
+                }
+                b'}' | b']' => {
+                    containers.pop();
+                    expect_key = false;
+                }
+                b',' => expect_key = containers.last().is_some_and(|open| open.is_object),
+                b':' => expect_key = false,
+                b'"' => {
+                    let start = i + 1;
+                    let end = string_end(payload, start);
+                    self.check_string(end - start)?;
+                    if expect_key && let Some(open) = containers.last_mut() {
+                        self.check_field(open.container, &payload[start..end])?;
+                        open.key = Some((start, end));
+                    }
+                    i = end;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    // like `check_json`, on a decoded value at nesting `depth`
+    fn check_value(&self, value: &Value, container: Container, depth: usize) -> Result<(), LimitError> {
+        match value {
+            Value::String(text) => self.check_string(text.len()),
+            Value::Array(values) => {
+                self.check_depth(depth)?;
+                values
+                    .iter()
+                    .try_for_each(|value| self.check_value(value, container.child(None), depth + 1))
+            }
+            Value::Object(fields) => {
+                self.check_depth(depth)?;
+                fields.iter().try_for_each(|(key, value)| {
+                    self.check_string(key.len())?;
+                    self.check_field(container, key.as_bytes())?;
+                    self.check_value(value, container.child(Some(key.as_bytes())), depth + 1)
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<(), LimitError> {
+        if self.max_depth > 0 && depth > self.max_depth {
+            return Err(LimitError::TooDeep(self.max_depth));
+        }
+        Ok(())
+    }
+
+    fn check_string(&self, len: usize) -> Result<(), LimitError> {
+        if self.max_string_len > 0 && len > self.max_string_len {
+            return Err(LimitError::StringTooLong(len, self.max_string_len));
+        }
+        Ok(())
+    }
+
+    fn check_field(&self, container: Container, key: &[u8]) -> Result<(), LimitError> {
+        if self.unknown_fields == UnknownFieldsAction::Reject
+            && !container.fields().iter().any(|field| field.as_bytes() == key)
+        {
+            return Err(LimitError::UnknownField(String::from_utf8_lossy(key).into_owned()));
+        }
+        Ok(())
+    }
+}
+
+// index of the closing quote of the string starting at `start`, skipping escaped characters,
+// or the end of the payload if the string is not terminated
+fn string_end(payload: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < payload.len() {
+        match payload[i] {
+            b'\\' => i += 2,
+            b'"' => return i,
+            _ => i += 1,
+        }
+    }
+    payload.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::limit_error::LimitError;
+    use crate::models::payload_format::PayloadFormat;
+    use crate::models::payload_limits::{PayloadLimits, UnknownFieldsAction};
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+
+    const PAYLOAD: &str = r#"{"deviceUuid": "246e3256-f0dd-4fcb-82c5-ee20c2267eeb", "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896", "apiToken": "473a4861-632b-4915-b01e-cf1d418966c6", "payload": {"value": "say \"hi\""}}"#;
+
+    #[test]
+    fn check_payload_limits() {
+        let limits = PayloadLimits {
+            unknown_fields: UnknownFieldsAction::Reject,
+            ..PayloadLimits::default()
+        };
+        assert_eq!(limits.check(PAYLOAD.as_bytes(), PayloadFormat::Json), Ok(()));
+        let multi = r#"{"deviceUuid": "d", "apiToken": "t", "readings": [{"featureName": "temperature", "featureUuid": "f", "payload": {"value": 21.5}, "unit": "C"}]}"#;
+        assert_eq!(limits.check(multi.as_bytes(), PayloadFormat::Json), Ok(()));
+
+        let small = PayloadLimits {
+            max_bytes: 64,
+            ..limits
+        };
+        assert_eq!(
+            small.check(PAYLOAD.as_bytes(), PayloadFormat::Json),
+            Err(LimitError::PayloadTooLarge(PAYLOAD.len(), 64))
+        );
+        assert_eq!(
+            small.check(&[0xa1; 65], PayloadFormat::Cbor),
+            Err(LimitError::PayloadTooLarge(65, 64))
+        );
+        // invalid binary payloads are left to the decoder
+        assert_eq!(limits.check(&[0x81, 0xa1], PayloadFormat::MessagePack), Ok(()));
+    }
+
+    #[test]
+    fn check_binary_structure_limits() {
+        let limits = PayloadLimits {
+            max_depth: 4,
+            max_string_len: 36,
+            unknown_fields: UnknownFieldsAction::Reject,
+            ..PayloadLimits::default()
+        };
+        let encode = |value: &Value| {
+            let mut cbor = Vec::new();
+            ciborium::into_writer(value, &mut cbor).unwrap();
+            (cbor, rmp_serde::to_vec_named(value).unwrap())
+        };
+        let cases = [
+            (
+                json!({"deviceUuid": "d", "apiToken": "t", "values": [{"value": 1, "timestamp": 2}]}),
+                Ok(()),
+            ),
+            (json!({"payload": {"value": [[[1]]]}}), Err(LimitError::TooDeep(4))),
+            (
+                json!({"payload": {"value": "a".repeat(37)}}),
+                Err(LimitError::StringTooLong(37, 36)),
+            ),
+            (
+                json!({"payload": {"value": 1}, "extra": 2}),
+                Err(LimitError::UnknownField(String::from("extra"))),
+            ),
+        ];
+        for (value, expected) in cases {
+            let (cbor, msgpack) = encode(&value);
+            assert_eq!(limits.check(&cbor, PayloadFormat::Cbor), expected);
+            assert_eq!(limits.check(&msgpack, PayloadFormat::MessagePack), expected);
+        }
+    }
+
+    #[test]
+    fn wrong_field_at_other_level() {
+        let limits = PayloadLimits {
+            unknown_fields: UnknownFieldsAction::Reject,
+            ..PayloadLimits::default()
+        };
+        // fields valid in an object are unknown in the others
+        let cases = [
+            (r#"{"value": 1}"#, "value"),
+            (r#"{"payload": {"deviceUuid": "d"}}"#, "deviceUuid"),
+            (r#"{"values": [{"value": 1, "unit": "C"}]}"#, "unit"),
+            (r#"{"readings": [{"featureName": "f", "apiToken": "t"}]}"#, "apiToken"),
+            (r#"{"payload": {"value": {"value": 1}}}"#, "value"),
+        ];
+        for (payload, field) in cases {
+            let expected = Err(LimitError::UnknownField(String::from(field)));
+            assert_eq!(limits.check(payload.as_bytes(), PayloadFormat::Json), expected);
+            let value: Value = serde_json::from_str(payload).unwrap();
+            let msgpack = rmp_serde::to_vec_named(&value).unwrap();
+            assert_eq!(limits.check(&msgpack, PayloadFormat::MessagePack), expected);
+        }
+    }
+
+    #[test]
+    fn check_json_structure_limits() {
+        let limits = PayloadLimits {
+            max_depth: 4,
+            max_string_len: 36,
+            unknown_fields: UnknownFieldsAction::Reject,
+            ..PayloadLimits::default()
+        };
+        let deep = format!(r#"{{"payload": {{"value": {}1{}}}}}"#, "[".repeat(3), "]".repeat(3));
+        assert_eq!(
+            limits.check(deep.as_bytes(), PayloadFormat::Json),
+            Err(LimitError::TooDeep(4))
+        );
+        // brackets in strings are not nesting
+        let brackets = r#"{"payload": {"value": "[[[[{{{{"}}"#;
+        assert_eq!(limits.check(brackets.as_bytes(), PayloadFormat::Json), Ok(()));
+
+        let long = format!(r#"{{"payload": {{"value": "{}"}}}}"#, "a".repeat(37));
+        assert_eq!(
+            limits.check(long.as_bytes(), PayloadFormat::Json),
+            Err(LimitError::StringTooLong(37, 36))
+        );
+
+        let unknown = r#"{"payload": {"value": 1}, "extra": {"deviceUuid": 2}}"#;
+        assert_eq!(
+            limits.check(unknown.as_bytes(), PayloadFormat::Json),
+            Err(LimitError::UnknownField(String::from("extra")))
+        );
+        // string values are not keys
+        let value = r#"{"payload": {"value": "extra"}, "unit": "extra"}"#;
+        assert_eq!(limits.check(value.as_bytes(), PayloadFormat::Json), Ok(()));
+        let allowed = PayloadLimits {
+            unknown_fields: UnknownFieldsAction::Allow,
+            ..limits
+        };
+        assert_eq!(allowed.check(unknown.as_bytes(), PayloadFormat::Json), Ok(()));
+    }
+}
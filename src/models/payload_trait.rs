@@ -35,6 +35,26 @@ pub struct Online {
     pub value: i64,
 }
 
+// value of a feature described by the sensor registry.
+// Variants order matters, because serde tries them in sequence while deserializing,
+// so an integer is never read as a float.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FeatureValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+// payload decoded for every feature. Types, units and ranges of features come from the sensor registry
+// (see `SensorRegistry::default`), so sensor families added after the ones above, like co2, battery
+// or waterleak, are built-in features of the registry instead of new structs in this file.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeaturePayload {
+    pub value: FeatureValue,
+}
+
 pub trait PayloadTrait {}
 
 impl PayloadTrait for Temperature {}
@@ -50,3 +70,5 @@ impl PayloadTrait for Motion {}
 impl PayloadTrait for AirQuality {}
 
 impl PayloadTrait for Online {}
+
+impl PayloadTrait for FeaturePayload {}
@@ -0,0 +1,218 @@
+use chrono::TimeDelta;
+use tracing::info;
+
+use crate::config::Env;
+use crate::errors::config_error::ConfigError;
+use crate::models::batch::{BatchMode, BatchPolicy};
+use crate::models::coercion::CoercionMode;
+use crate::models::device_check::DeviceMismatchAction;
+use crate::models::device_registry::ReloadableDeviceRegistry;
+use crate::models::output_codec::OutputCodec;
+use crate::models::payload_limits::PayloadLimits;
+use crate::models::quarantine::{ErrorRatePolicy, Quarantine};
+use crate::models::rate_limit::RateLimiter;
+use crate::models::route::Route;
+use crate::models::schema_version::SchemaVersion;
+use crate::models::sensor_registry::SensorRegistry;
+use crate::models::signature::{SignatureMode, SignaturePolicy};
+use crate::models::timestamp::TimestampPolicy;
+use crate::models::token_policy::ApiTokenPolicy;
+use crate::models::topic::TopicTemplate;
+use crate::redact::RedactedFields;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcessingConfig {
+    pub registry: SensorRegistry,
+    pub topic_template: TopicTemplate,
+    // size and structure of payloads, checked before decoding
+    pub payload_limits: PayloadLimits,
+    pub timestamp_policy: TimestampPolicy,
+    pub batch_policy: BatchPolicy,
+    pub coercion: CoercionMode,
+    pub device_mismatch_action: DeviceMismatchAction,
+    // devices allowed to publish, None to accept all devices
+    pub devices: Option<ReloadableDeviceRegistry>,
+    // signatures of readings, verified with the signing secrets of the device registry
+    pub signature_policy: SignaturePolicy,
+    // limits of messages by device class, None to accept all messages
+    pub rate_limiter: Option<RateLimiter>,
+    // devices whose messages are dropped
+    pub quarantine: Quarantine,
+    // MQTT topic to release devices from quarantine, None if releases are not allowed
+    pub quarantine_release_topic: Option<String>,
+    // key of operators to verify releases, see `ReleaseRequest`
+    pub quarantine_release_key: Vec<u8>,
+    // queue for quarantine events, None to only log them
+    pub management_queue_name: Option<String>,
+    pub output_codec: OutputCodec,
+    // version of messages published to the main queue
+    pub schema_version: SchemaVersion,
+    // additional queues, each one with its own schema version
+    pub routes: Vec<Route>,
+    // how apiToken is forwarded to the main queue and to routes without their own policy
+    pub api_token_policy: ApiTokenPolicy,
+    // key of the `hash` policy
+    pub api_token_hash_key: Vec<u8>,
+    // queue for readings rejected by validation, None to drop them
+    pub rejects_queue_name: Option<String>,
+    // JSON fields masked when payloads are logged
+    pub redacted_fields: RedactedFields,
+}
+
+impl ProcessingConfig {
+    // MQTT topics to subscribe to receive all registered features
+    // and, if defined, the topic to release devices from quarantine
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics = self.registry.topics(&self.topic_template);
+        topics.extend(self.quarantine_release_topic.clone());
+        topics
+    }
+
+    pub fn new(env: &Env) -> Result<Self, ConfigError> {
+        let registry = if env.sensor_registry_file.is_empty() {
+            info!(target: "app", "SENSOR_REGISTRY_FILE not defined, using built-in features");
+            SensorRegistry::default()
+        } else {
+            SensorRegistry::load(&env.sensor_registry_file)?
+        };
+        let devices = if env.device_registry_file.is_empty() {
+            info!(target: "app", "DEVICE_REGISTRY_FILE not defined, API tokens are not verified");
+            None
+        } else {
+            Some(ReloadableDeviceRegistry::load(&env.device_registry_file)?)
+        };
+        if devices.is_none() && env.signature_mode == SignatureMode::Strict {
+            return Err(ConfigError::MissingDeviceRegistry(String::from(
+                "SIGNATURE_MODE=strict",
+            )));
+        }
+        let rate_limiter = if env.rate_limits_file.is_empty() {
+            None
+        } else {
+            Some(RateLimiter::load(&env.rate_limits_file)?)
+        };
+        let routes = if env.routes_file.is_empty() {
+            vec![]
+        } else {
+            Route::load(&env.routes_file)?
+        };
+        if env.api_token_hash_key.is_empty() {
+            if env.api_token_policy.needs_hash_key() {
+                return Err(ConfigError::MissingHashKey(String::from("API_TOKEN_POLICY=hash")));
+            }
+            if let Some(route) = routes
+                .iter()
+                .find(|route| route.api_token_policy.is_some_and(|policy| policy.needs_hash_key()))
+            {
+                return Err(ConfigError::MissingHashKey(format!("route {}", route.name)));
+            }
+        }
+        // without a key, anyone publishing on the broker could release any device
+        if !env.quarantine_release_topic.is_empty() && env.quarantine_release_key.is_empty() {
+            return Err(ConfigError::MissingReleaseKey(String::from("QUARANTINE_RELEASE_TOPIC")));
+        }
+        // batch envelopes didn't exist before version 2, so they can't be sent to V1 consumers
+        if env.batch_mode == BatchMode::Envelope {
+            if env.schema_version == SchemaVersion::V1 {
+                return Err(ConfigError::BatchSchemaVersion(String::from("SCHEMA_VERSION")));
+            }
+            if let Some(route) = routes.iter().find(|route| route.schema_version == SchemaVersion::V1) {
+                return Err(ConfigError::BatchSchemaVersion(format!("route {}", route.name)));
+            }
+        }
+        let topic_template =
+            TopicTemplate::new(&env.topic_template)?.with_device_uuid_validation(env.topic_validate_device_uuid);
+        Ok(Self {
+            registry,
+            topic_template,
+            payload_limits: PayloadLimits {
+                max_bytes: env.payload_max_bytes,
+                max_depth: env.payload_max_depth,
+                max_string_len: env.payload_max_string_len,
+                unknown_fields: env.payload_unknown_fields,
+            },
+            timestamp_policy: TimestampPolicy {
+                action: env.timestamp_skew_action,
+                max_skew: TimeDelta::seconds(env.timestamp_max_skew_secs),
+            },
+            batch_policy: BatchPolicy {
+                mode: env.batch_mode,
+                max_size: env.batch_max_size,
+                max_age: TimeDelta::seconds(env.batch_max_age_secs),
+            },
+            coercion: env.value_coercion,
+            device_mismatch_action: env.device_mismatch_action,
+            devices,
+            signature_policy: SignaturePolicy::new(env.signature_mode, TimeDelta::seconds(env.signature_max_age_secs)),
+            rate_limiter,
+            quarantine: Quarantine::new(ErrorRatePolicy {
+                threshold: env.quarantine_error_threshold,
+                window: TimeDelta::seconds(env.quarantine_error_window_secs),
+                duration: TimeDelta::seconds(env.quarantine_secs),
+            }),
+            quarantine_release_topic: Some(env.quarantine_release_topic.clone()).filter(|topic| !topic.is_empty()),
+            quarantine_release_key: env.quarantine_release_key.as_bytes().to_vec(),
+            management_queue_name: Some(env.amqp_management_queue_name.clone()).filter(|name| !name.is_empty()),
+            output_codec: env.output_codec,
+            schema_version: env.schema_version,
+            routes,
+            api_token_policy: env.api_token_policy,
+            api_token_hash_key: env.api_token_hash_key.as_bytes().to_vec(),
+            rejects_queue_name: Some(env.amqp_rejects_queue_name.clone()).filter(|name| !name.is_empty()),
+            redacted_fields: RedactedFields::new(env.log_redacted_fields.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::tests::get_env;
+    use crate::models::processing_config::ProcessingConfig;
+    use pretty_assertions::assert_eq;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn wrong_batch_envelope_schema_version_v1() {
+        let path = std::env::temp_dir().join("wrong_batch_envelope_schema_version_v1.json");
+        write(
+            &path,
+            r#"{"routes": [{"name": "legacy", "queue": "ks89_v1", "features": ["temperature"], "schemaVersion": 1}]}"#,
+        )
+        .unwrap();
+        let routes_file = path.to_str().unwrap();
+
+        // batches are expanded to V1 messages
+        let env = get_env(&[("ROUTES_FILE", routes_file), ("SCHEMA_VERSION", "1")]);
+        assert!(ProcessingConfig::new(&env).is_ok());
+
+        let env = get_env(&[("ROUTES_FILE", routes_file), ("BATCH_MODE", "envelope")]);
+        assert_eq!(
+            ProcessingConfig::new(&env).err().unwrap().to_string(),
+            "BATCH_MODE=envelope requires schema version 2, but route legacy uses version 1"
+        );
+        let env = get_env(&[("BATCH_MODE", "envelope"), ("SCHEMA_VERSION", "1")]);
+        assert_eq!(
+            ProcessingConfig::new(&env).err().unwrap().to_string(),
+            "BATCH_MODE=envelope requires schema version 2, but SCHEMA_VERSION uses version 1"
+        );
+        let env = get_env(&[("BATCH_MODE", "envelope")]);
+        assert!(ProcessingConfig::new(&env).is_ok());
+
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wrong_quarantine_release_without_key() {
+        let env = get_env(&[("QUARANTINE_RELEASE_TOPIC", "quarantine/release")]);
+        assert_eq!(
+            ProcessingConfig::new(&env).err().unwrap().to_string(),
+            "QUARANTINE_RELEASE_KEY is required by QUARANTINE_RELEASE_TOPIC"
+        );
+        let env = get_env(&[
+            ("QUARANTINE_RELEASE_TOPIC", "quarantine/release"),
+            ("QUARANTINE_RELEASE_KEY", "operator-key"),
+        ]);
+        let config = ProcessingConfig::new(&env).unwrap();
+        assert_eq!(config.quarantine_release_key, b"operator-key");
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+
+use crate::models::message::{BatchMessage as JsonBatchMessage, BatchValue as JsonBatchValue, Message as JsonMessage};
+use crate::models::payload_trait::{FeaturePayload, FeatureValue};
+use crate::models::schema_version::SchemaVersion;
+use crate::models::topic::Topic as JsonTopic;
+
+// Protobuf messages defined in proto/message.proto.
+// They are written by hand, instead of generating them with prost-build,
+// to avoid a dependency on protoc at build time.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Topic {
+    #[prost(string, tag = "1")]
+    pub family: String,
+    #[prost(string, tag = "2")]
+    pub device_id: String,
+    #[prost(string, tag = "3")]
+    pub feature_name: String,
+    #[prost(btree_map = "string, string", tag = "4")]
+    pub segments: BTreeMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Kind {
+    #[prost(int64, tag = "1")]
+    IntValue(i64),
+    #[prost(double, tag = "2")]
+    FloatValue(f64),
+    #[prost(bool, tag = "3")]
+    BoolValue(bool),
+    #[prost(string, tag = "4")]
+    StringValue(String),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Value {
+    #[prost(oneof = "Kind", tags = "1, 2, 3, 4")]
+    pub kind: Option<Kind>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Payload {
+    #[prost(message, optional, tag = "1")]
+    pub value: Option<Value>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Message {
+    #[prost(string, tag = "1")]
+    pub api_token: String,
+    #[prost(string, tag = "2")]
+    pub device_uuid: String,
+    #[prost(string, tag = "3")]
+    pub feature_uuid: String,
+    #[prost(message, optional, tag = "4")]
+    pub topic: Option<Topic>,
+    #[prost(message, optional, tag = "5")]
+    pub payload: Option<Payload>,
+    #[prost(string, optional, tag = "6")]
+    pub unit: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub original_unit: Option<String>,
+    #[prost(int64, optional, tag = "8")]
+    pub timestamp_ms: Option<i64>,
+    #[prost(int64, tag = "9")]
+    pub received_at_ms: i64,
+    #[prost(uint32, tag = "10")]
+    pub schema_version: u32,
+    #[prost(bool, tag = "11")]
+    pub device_mismatch: bool,
+    #[prost(bool, tag = "12")]
+    pub signature_verified: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BatchValue {
+    #[prost(message, optional, tag = "1")]
+    pub value: Option<Value>,
+    #[prost(int64, optional, tag = "2")]
+    pub timestamp_ms: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BatchMessage {
+    #[prost(string, tag = "1")]
+    pub api_token: String,
+    #[prost(string, tag = "2")]
+    pub device_uuid: String,
+    #[prost(string, tag = "3")]
+    pub feature_uuid: String,
+    #[prost(message, optional, tag = "4")]
+    pub topic: Option<Topic>,
+    #[prost(message, repeated, tag = "5")]
+    pub values: Vec<BatchValue>,
+    #[prost(string, optional, tag = "6")]
+    pub unit: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub original_unit: Option<String>,
+    #[prost(int64, tag = "8")]
+    pub received_at_ms: i64,
+    #[prost(uint32, tag = "9")]
+    pub schema_version: u32,
+    #[prost(bool, tag = "10")]
+    pub device_mismatch: bool,
+}
+
+impl From<&JsonTopic> for Topic {
+    fn from(topic: &JsonTopic) -> Self {
+        Self {
+            family: topic.family.clone(),
+            device_id: topic.device_id.clone(),
+            feature_name: topic.feature_name.clone(),
+            segments: topic.segments.clone(),
+        }
+    }
+}
+
+impl From<&FeatureValue> for Value {
+    fn from(value: &FeatureValue) -> Self {
+        let kind = match value {
+            FeatureValue::Int(value) => Kind::IntValue(*value),
+            FeatureValue::Float(value) => Kind::FloatValue(*value),
+            FeatureValue::Bool(value) => Kind::BoolValue(*value),
+            FeatureValue::String(value) => Kind::StringValue(value.clone()),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+impl Message {
+    // fields added after the requested version are left empty
+    pub fn with_version(message: &JsonMessage<FeaturePayload>, version: SchemaVersion) -> Self {
+        let mut proto_message = Self::from(message);
+        if version == SchemaVersion::V1 {
+            proto_message.unit = None;
+            proto_message.original_unit = None;
+            proto_message.timestamp_ms = None;
+            proto_message.received_at_ms = 0;
+            proto_message.device_mismatch = false;
+            proto_message.signature_verified = false;
+            if let Some(topic) = proto_message.topic.as_mut() {
+                topic.segments.clear();
+            }
+        }
+        proto_message.schema_version = u8::from(version).into();
+        proto_message
+    }
+}
+
+impl From<&JsonMessage<FeaturePayload>> for Message {
+    fn from(message: &JsonMessage<FeaturePayload>) -> Self {
+        Self {
+            api_token: message.api_token.clone(),
+            device_uuid: message.device_uuid.clone(),
+            feature_uuid: message.feature_uuid.clone(),
+            topic: Some(Topic::from(&message.topic)),
+            payload: Some(Payload {
+                value: Some(Value::from(&message.payload.value)),
+            }),
+            unit: message.unit.clone(),
+            original_unit: message.original_unit.clone(),
+            timestamp_ms: message.timestamp.map(|ts| ts.timestamp_millis()),
+            received_at_ms: message.received_at.timestamp_millis(),
+            schema_version: u8::from(message.schema_version).into(),
+            device_mismatch: message.device_mismatch,
+            signature_verified: message.signature_verified,
+        }
+    }
+}
+
+impl From<&JsonBatchValue> for BatchValue {
+    fn from(batch_value: &JsonBatchValue) -> Self {
+        Self {
+            value: Some(Value::from(&batch_value.value)),
+            timestamp_ms: batch_value.timestamp.map(|ts| ts.timestamp_millis()),
+        }
+    }
+}
+
+impl From<&JsonBatchMessage> for BatchMessage {
+    fn from(message: &JsonBatchMessage) -> Self {
+        Self {
+            api_token: message.api_token.clone(),
+            device_uuid: message.device_uuid.clone(),
+            feature_uuid: message.feature_uuid.clone(),
+            topic: Some(Topic::from(&message.topic)),
+            values: message.values.iter().map(BatchValue::from).collect(),
+            unit: message.unit.clone(),
+            original_unit: message.original_unit.clone(),
+            received_at_ms: message.received_at.timestamp_millis(),
+            schema_version: u8::from(message.schema_version).into(),
+            device_mismatch: message.device_mismatch,
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{info, warn};
+
+use crate::errors::release_error::ReleaseError;
+
+// why a device entered or left the quarantine
+pub const REASON_ERROR_RATE: &str = "error_rate";
+pub const REASON_RATE_LIMIT: &str = "rate_limit";
+pub const REASON_TIMEOUT: &str = "timeout";
+pub const REASON_OPERATOR: &str = "operator";
+
+// error windows and expired quarantines are pruned when they reach this number of devices, at least
+const MIN_ERRORS_PRUNE_SIZE: usize = 1024;
+const MIN_DEVICES_PRUNE_SIZE: usize = 1024;
+
+// quarantine of devices sending too many invalid messages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorRatePolicy {
+    // errors in `window` that quarantine the device, 0 to never quarantine it
+    pub threshold: u32,
+    pub window: TimeDelta,
+    // how long the device stays in quarantine
+    pub duration: TimeDelta,
+}
+
+impl Default for ErrorRatePolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 0,
+            window: TimeDelta::minutes(1),
+            duration: TimeDelta::hours(1),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuarantineEventKind {
+    Quarantined,
+    Released,
+}
+
+// event published to the management queue when a device enters or leaves the quarantine
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantineEvent {
+    pub event: QuarantineEventKind,
+    pub device_id: String,
+    pub reason: &'static str,
+    // end of the quarantine, only for quarantined devices
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    pub at: DateTime<Utc>,
+}
+
+impl QuarantineEvent {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+// release of a device published by an operator on the release topic, signed with the operator key.
+// The operator signs the text returned by `signed_content` and sends the hex HMAC-SHA256 in `signature`,
+// so devices, which can publish on the broker too, cannot release themselves.
+// For example {"deviceId": "246e3256-...", "timestamp": "2025-12-25T10:30:00Z", "signature": "9f1c..."}
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseRequest {
+    pub device_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub signature: String,
+}
+
+impl ReleaseRequest {
+    // text signed by the operator: "release", deviceId and timestamp as epoch milliseconds,
+    // separated by new lines. For example "release\n246e3256-...\n1766658600000"
+    pub fn signed_content(device_id: &str, timestamp: DateTime<Utc>) -> String {
+        format!("release\n{}\n{}", device_id, timestamp.timestamp_millis())
+    }
+
+    // parses the request and verifies its signature with `key`. The timestamp must be within `max_age`
+    // of the receive time, so a captured request cannot be replayed later.
+    pub fn verify(payload: &[u8], key: &[u8], max_age: TimeDelta, now: DateTime<Utc>) -> Result<Self, ReleaseError> {
+        let request: ReleaseRequest = serde_json::from_slice(payload).map_err(ReleaseError::Parse)?;
+        let invalid = || ReleaseError::InvalidSignature(request.device_id.clone());
+        // an empty key would accept signatures computed by anyone
+        if key.is_empty() {
+            return Err(invalid());
+        }
+        let age = now - request.timestamp;
+        if age.abs() > max_age {
+            return Err(ReleaseError::Expired(request.device_id.clone(), age.num_seconds()));
+        }
+        let signature = hex::decode(request.signature.trim()).map_err(|_| invalid())?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(Self::signed_content(&request.device_id, request.timestamp).as_bytes());
+        // constant-time comparison
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+        Ok(request)
+    }
+}
+
+#[derive(Debug, Default)]
+struct QuarantineState {
+    // end of the quarantine by device id
+    devices: HashMap<String, DateTime<Utc>>,
+    devices_prune_size: usize,
+    // start of the current window and errors in it, by device id
+    errors: HashMap<String, (DateTime<Utc>, u32)>,
+    errors_prune_size: usize,
+    // events not published yet
+    events: Vec<QuarantineEvent>,
+}
+
+// devices whose messages are dropped until a deadline, because they flooded the broker
+// or sent too many invalid messages. Clones share the same devices.
+#[derive(Debug, Clone, Default)]
+pub struct Quarantine {
+    pub error_policy: ErrorRatePolicy,
+    state: Arc<Mutex<QuarantineState>>,
+}
+
+impl Quarantine {
+    pub fn new(error_policy: ErrorRatePolicy) -> Self {
+        Self {
+            error_policy,
+            ..Self::default()
+        }
+    }
+
+    // quarantines the device until `until`, extending the current quarantine if it ends earlier
+    pub fn add(&self, device_id: &str, until: DateTime<Utc>, reason: &'static str, now: DateTime<Utc>) {
+        let mut state = self.state.lock().unwrap();
+        // devices that never publish again would be released only here, with an event as on timeout
+        if state.devices.len() >= state.devices_prune_size.max(MIN_DEVICES_PRUNE_SIZE) {
+            let expired: Vec<String> = state
+                .devices
+                .iter()
+                .filter(|(_, until)| **until <= now)
+                .map(|(device_id, _)| device_id.clone())
+                .collect();
+            for device_id in expired {
+                release(&mut state, device_id, REASON_TIMEOUT, now);
+            }
+            state.devices_prune_size = state.devices.len() * 2;
+        }
+        let device_id = device_id.to_lowercase();
+        let current = state.devices.entry(device_id.clone()).or_insert(until);
+        if *current < until {
+            *current = until;
+        }
+        let until = *current;
+        warn!(target: "app", "add - device {} quarantined until {}, reason = {}", &device_id, until, reason);
+        state.errors.remove(&device_id);
+        state.events.push(QuarantineEvent {
+            event: QuarantineEventKind::Quarantined,
+            device_id,
+            reason,
+            until: Some(until),
+            at: now,
+        });
+    }
+
+    // true if the device is quarantined at `now`; expired quarantines are released, with an event
+    pub fn is_quarantined(&self, device_id: &str, now: DateTime<Utc>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.devices.is_empty() {
+            return false;
+        }
+        let device_id = device_id.to_lowercase();
+        match state.devices.get(&device_id) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                release(&mut state, device_id, REASON_TIMEOUT, now);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // releases the device before the end of the quarantine and returns true if it was quarantined
+    pub fn release(&self, device_id: &str, now: DateTime<Utc>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let device_id = device_id.to_lowercase();
+        if !state.devices.contains_key(&device_id) {
+            return false;
+        }
+        release(&mut state, device_id, REASON_OPERATOR, now);
+        true
+    }
+
+    // counts an invalid message of the device and quarantines it if there are too many in the window.
+    // Returns true if the device was quarantined.
+    pub fn record_error(&self, device_id: &str, now: DateTime<Utc>) -> bool {
+        let policy = self.error_policy;
+        if policy.threshold == 0 {
+            return false;
+        }
+        let errors = {
+            let mut state = self.state.lock().unwrap();
+            // expired windows would be reset by the next error anyway, so they can be forgotten
+            if state.errors.len() >= state.errors_prune_size.max(MIN_ERRORS_PRUNE_SIZE) {
+                state
+                    .errors
+                    .retain(|_, (window_start, _)| now - *window_start < policy.window);
+                state.errors_prune_size = state.errors.len() * 2;
+            }
+            let (window_start, errors) = state.errors.entry(device_id.to_lowercase()).or_insert((now, 0));
+            if now - *window_start >= policy.window {
+                *window_start = now;
+                *errors = 0;
+            }
+            *errors += 1;
+            *errors
+        };
+        if errors < policy.threshold {
+            return false;
+        }
+        self.add(device_id, now + policy.duration, REASON_ERROR_RATE, now);
+        true
+    }
+
+    // events since the last call, to publish them to the management queue
+    pub fn take_events(&self) -> Vec<QuarantineEvent> {
+        std::mem::take(&mut self.state.lock().unwrap().events)
+    }
+}
+
+fn release(state: &mut QuarantineState, device_id: String, reason: &'static str, now: DateTime<Utc>) {
+    info!(target: "app", "release - device {} released from quarantine, reason = {}", &device_id, reason);
+    state.devices.remove(&device_id);
+    state.events.push(QuarantineEvent {
+        event: QuarantineEventKind::Released,
+        device_id,
+        reason,
+        until: None,
+        at: now,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::release_error::ReleaseError;
+    use crate::models::quarantine::{
+        ErrorRatePolicy, MIN_DEVICES_PRUNE_SIZE, MIN_ERRORS_PRUNE_SIZE, Quarantine, QuarantineEvent,
+        QuarantineEventKind, REASON_ERROR_RATE, REASON_OPERATOR, REASON_RATE_LIMIT, REASON_TIMEOUT, ReleaseRequest,
+    };
+    use chrono::{DateTime, TimeDelta, Utc};
+    use hmac::{Hmac, Mac};
+    use pretty_assertions::assert_eq;
+    use sha2::Sha256;
+
+    fn now() -> DateTime<Utc> {
+        "2025-12-25T10:30:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn check_quarantine() {
+        let quarantine = Quarantine::default();
+        let shared = quarantine.clone();
+        assert!(!quarantine.is_quarantined("device-1", now()));
+
+        quarantine.add("device-1", now() + TimeDelta::seconds(60), REASON_RATE_LIMIT, now());
+        // an earlier deadline doesn't shorten the quarantine
+        quarantine.add("DEVICE-1", now() + TimeDelta::seconds(10), REASON_RATE_LIMIT, now());
+        assert!(shared.is_quarantined("device-1", now() + TimeDelta::seconds(30)));
+        assert!(!shared.is_quarantined("device-2", now()));
+        assert!(!shared.is_quarantined("device-1", now() + TimeDelta::seconds(60)));
+
+        let events = quarantine.take_events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[1].until, Some(now() + TimeDelta::seconds(60)));
+        assert_eq!(
+            events[2],
+            QuarantineEvent {
+                event: QuarantineEventKind::Released,
+                device_id: "device-1".to_string(),
+                reason: REASON_TIMEOUT,
+                until: None,
+                at: now() + TimeDelta::seconds(60),
+            }
+        );
+        assert!(quarantine.take_events().is_empty());
+    }
+
+    #[test]
+    fn check_quarantine_error_rate() {
+        let quarantine = Quarantine::new(ErrorRatePolicy {
+            threshold: 3,
+            window: TimeDelta::seconds(60),
+            duration: TimeDelta::hours(1),
+        });
+        assert!(!quarantine.record_error("device-1", now()));
+        assert!(!quarantine.record_error("device-1", now() + TimeDelta::seconds(30)));
+        // a new window
+        assert!(!quarantine.record_error("device-1", now() + TimeDelta::seconds(60)));
+        assert!(!quarantine.record_error("device-1", now() + TimeDelta::seconds(70)));
+        assert!(!quarantine.record_error("device-2", now() + TimeDelta::seconds(70)));
+        assert!(quarantine.record_error("device-1", now() + TimeDelta::seconds(80)));
+        assert!(quarantine.is_quarantined("device-1", now() + TimeDelta::seconds(90)));
+        assert!(!quarantine.is_quarantined("device-2", now() + TimeDelta::seconds(90)));
+
+        let events = quarantine.take_events();
+        assert_eq!(
+            events[0].to_json(),
+            r#"{"event":"quarantined","deviceId":"device-1","reason":"error_rate","until":"2025-12-25T11:31:20Z","at":"2025-12-25T10:31:20Z"}"#
+        );
+        assert_eq!(events[0].reason, REASON_ERROR_RATE);
+
+        // released by an operator
+        assert!(quarantine.release("device-1", now() + TimeDelta::seconds(100)));
+        assert!(!quarantine.release("device-1", now() + TimeDelta::seconds(100)));
+        assert!(!quarantine.is_quarantined("device-1", now() + TimeDelta::seconds(110)));
+        assert_eq!(quarantine.take_events()[0].reason, REASON_OPERATOR);
+    }
+
+    #[test]
+    fn check_quarantine_devices_pruning() {
+        let quarantine = Quarantine::default();
+        quarantine.add("device-long", now() + TimeDelta::hours(1), REASON_RATE_LIMIT, now());
+        for i in 1..MIN_DEVICES_PRUNE_SIZE {
+            quarantine.add(
+                &format!("device-{}", i),
+                now() + TimeDelta::seconds(60),
+                REASON_RATE_LIMIT,
+                now(),
+            );
+        }
+        quarantine.take_events();
+        assert_eq!(quarantine.state.lock().unwrap().devices.len(), MIN_DEVICES_PRUNE_SIZE);
+
+        // expired quarantines are released, also without new messages of their devices,
+        // while quarantines still running are kept
+        quarantine.add(
+            "device-new",
+            now() + TimeDelta::hours(1),
+            REASON_RATE_LIMIT,
+            now() + TimeDelta::seconds(60),
+        );
+        assert_eq!(quarantine.state.lock().unwrap().devices.len(), 2);
+        let events = quarantine.take_events();
+        assert_eq!(events.len(), MIN_DEVICES_PRUNE_SIZE);
+        assert!(
+            events[..MIN_DEVICES_PRUNE_SIZE - 1]
+                .iter()
+                .all(|event| event.reason == REASON_TIMEOUT)
+        );
+        assert!(quarantine.is_quarantined("device-long", now() + TimeDelta::seconds(60)));
+    }
+
+    #[test]
+    fn check_quarantine_errors_pruning() {
+        let quarantine = Quarantine::new(ErrorRatePolicy {
+            threshold: 3,
+            window: TimeDelta::seconds(60),
+            duration: TimeDelta::hours(1),
+        });
+        for i in 0..MIN_ERRORS_PRUNE_SIZE {
+            quarantine.record_error(&format!("device-{}", i), now());
+        }
+        assert_eq!(quarantine.state.lock().unwrap().errors.len(), MIN_ERRORS_PRUNE_SIZE);
+
+        // expired windows are removed
+        assert!(!quarantine.record_error("device-new", now() + TimeDelta::seconds(60)));
+        assert_eq!(quarantine.state.lock().unwrap().errors.len(), 1);
+
+        // windows still open are kept
+        for i in 1..MIN_ERRORS_PRUNE_SIZE {
+            quarantine.record_error(&format!("device-{}", i), now() + TimeDelta::seconds(60));
+        }
+        assert!(!quarantine.record_error("device-new", now() + TimeDelta::seconds(90)));
+        assert_eq!(quarantine.state.lock().unwrap().errors.len(), MIN_ERRORS_PRUNE_SIZE);
+    }
+
+    const RELEASE_KEY: &[u8] = b"operator-key";
+
+    fn release_payload(device_id: &str, timestamp: &str, key: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(ReleaseRequest::signed_content(device_id, timestamp.parse().unwrap()).as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        format!(
+            r#"{{"deviceId": "{}", "timestamp": "{}", "signature": "{}"}}"#,
+            device_id, timestamp, signature
+        )
+    }
+
+    #[test]
+    fn ok_verify_release_request() {
+        let payload = release_payload("device-1", "2025-12-25T10:29:00Z", RELEASE_KEY);
+        let request = ReleaseRequest::verify(payload.as_bytes(), RELEASE_KEY, TimeDelta::minutes(5), now()).unwrap();
+        assert_eq!(request.device_id, "device-1");
+        assert_eq!(
+            ReleaseRequest::signed_content("device-1", request.timestamp),
+            "release\ndevice-1\n1766658540000"
+        );
+    }
+
+    #[test]
+    fn wrong_verify_release_request() {
+        let max_age = TimeDelta::minutes(5);
+        // unsigned, like the device id alone published by a device
+        let res = ReleaseRequest::verify(b"device-1", RELEASE_KEY, max_age, now());
+        assert!(matches!(res, Err(ReleaseError::Parse(_))));
+        let unsigned = r#"{"deviceId": "device-1", "timestamp": "2025-12-25T10:30:00Z"}"#;
+        let res = ReleaseRequest::verify(unsigned.as_bytes(), RELEASE_KEY, max_age, now());
+        assert!(matches!(res, Err(ReleaseError::Parse(_))));
+
+        // signed with another key
+        let payload = release_payload("device-1", "2025-12-25T10:30:00Z", b"device-key");
+        let res = ReleaseRequest::verify(payload.as_bytes(), RELEASE_KEY, max_age, now());
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "release request of device device-1 has an invalid signature"
+        );
+        // for another device
+        let payload = release_payload("device-1", "2025-12-25T10:30:00Z", RELEASE_KEY).replace("device-1", "device-2");
+        let res = ReleaseRequest::verify(payload.as_bytes(), RELEASE_KEY, max_age, now());
+        assert!(matches!(res, Err(ReleaseError::InvalidSignature(_))));
+        // without key
+        let payload = release_payload("device-1", "2025-12-25T10:30:00Z", b"");
+        let res = ReleaseRequest::verify(payload.as_bytes(), b"", max_age, now());
+        assert!(matches!(res, Err(ReleaseError::InvalidSignature(_))));
+
+        // replayed later
+        let payload = release_payload("device-1", "2025-12-25T10:20:00Z", RELEASE_KEY);
+        let res = ReleaseRequest::verify(payload.as_bytes(), RELEASE_KEY, max_age, now());
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "release request of device device-1 expired, signed 600 seconds from the receive time"
+        );
+    }
+}
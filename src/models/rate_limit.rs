@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::errors::message_error::RejectReason;
+use crate::errors::rate_limit_error::RateLimitError;
+use crate::models::quarantine::{Quarantine, REASON_RATE_LIMIT};
+
+// class of devices without a class in the device registry
+pub const DEFAULT_DEVICE_CLASS: &str = "default";
+
+// buckets are pruned when they reach this number, at least
+const MIN_BUCKETS_PRUNE_SIZE: usize = 1024;
+
+// what to do with messages over the limit
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitAction {
+    // drop messages over the limit
+    #[default]
+    Drop,
+    // forward one message every `sampleEvery` over the limit
+    Sample,
+    // drop all messages of the device for `quarantineSecs`
+    Quarantine,
+}
+
+// token bucket: up to `burst` messages at once, refilled with `ratePerSec` messages per second
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LimitSpec {
+    pub burst: u32,
+    pub rate_per_sec: f64,
+    #[serde(default)]
+    pub action: LimitAction,
+    #[serde(default = "default_sample_every")]
+    pub sample_every: u64,
+    #[serde(default = "default_quarantine_secs")]
+    pub quarantine_secs: i64,
+}
+
+fn default_sample_every() -> u64 {
+    10
+}
+
+fn default_quarantine_secs() -> i64 {
+    300
+}
+
+// limits of a device class, for all messages of the device and for each of its features
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ClassLimits {
+    #[serde(default)]
+    pub device: Option<LimitSpec>,
+    #[serde(default)]
+    pub feature: Option<LimitSpec>,
+}
+
+#[derive(Deserialize)]
+struct RateLimitsFile {
+    classes: HashMap<String, ClassLimits>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+    // messages over the limit, to sample them
+    exceeded: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &LimitSpec, now: DateTime<Utc>) {
+        let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        self.tokens = (self.tokens + elapsed * limit.rate_per_sec).min(f64::from(limit.burst));
+        self.updated_at = now;
+    }
+}
+
+// buckets by device id and feature name, None for the bucket of the whole device
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<(String, Option<String>), Bucket>,
+    prune_size: usize,
+}
+
+impl Buckets {
+    // removes full buckets, that behave like new ones, so devices seen once don't use memory forever
+    fn prune(&mut self, max_refill: TimeDelta, now: DateTime<Utc>) {
+        if self.buckets.len() < self.prune_size.max(MIN_BUCKETS_PRUNE_SIZE) {
+            return;
+        }
+        self.buckets.retain(|_, bucket| now - bucket.updated_at < max_refill);
+        self.prune_size = self.buckets.len() * 2;
+    }
+}
+
+// token-bucket limits of MQTT messages by device class, applied before decoding payloads.
+// Clones share the same buckets.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    classes: HashMap<String, ClassLimits>,
+    // time to refill the largest bucket, after which idle buckets are full
+    max_refill: TimeDelta,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(classes: HashMap<String, ClassLimits>) -> Self {
+        let max_refill_secs = classes
+            .values()
+            .flat_map(|limits| [&limits.device, &limits.feature])
+            .flatten()
+            .map(|limit| f64::from(limit.burst) / limit.rate_per_sec)
+            .fold(0.0, f64::max);
+        Self {
+            classes,
+            max_refill: TimeDelta::milliseconds((max_refill_secs * 1000.0).ceil() as i64),
+            ..Self::default()
+        }
+    }
+
+    // load limits from a JSON file with the format `{"classes": {"default": {"device": {...}, "feature": {...}}}}`
+    pub fn load(path: &str) -> Result<Self, RateLimitError> {
+        info!(target: "app", "load - loading rate limits from file {}", path);
+        let content = read_to_string(path).map_err(|err| RateLimitError::Read(path.to_string(), err))?;
+        let file: RateLimitsFile =
+            serde_json::from_str(&content).map_err(|err| RateLimitError::Parse(path.to_string(), err))?;
+        for (class, limits) in &file.classes {
+            for limit in [&limits.device, &limits.feature].into_iter().flatten() {
+                let invalid = |reason: &str| RateLimitError::InvalidLimit(class.clone(), reason.to_string());
+                if limit.burst == 0 {
+                    return Err(invalid("burst must be at least 1"));
+                }
+                if !limit.rate_per_sec.is_finite() || limit.rate_per_sec <= 0.0 {
+                    return Err(invalid("ratePerSec must be greater than 0"));
+                }
+                if limit.sample_every == 0 {
+                    return Err(invalid("sampleEvery must be at least 1"));
+                }
+                if limit.quarantine_secs <= 0 {
+                    return Err(invalid("quarantineSecs must be greater than 0"));
+                }
+            }
+        }
+        Ok(Self::new(file.classes))
+    }
+
+    // takes a token from the buckets of the device and of the feature, with the limits of `class`.
+    // Returns the reason why the message must be dropped, if it's over a limit.
+    // With the quarantine action the device is also added to `quarantine`.
+    pub fn check(
+        &self,
+        device_id: &str,
+        feature_name: &str,
+        class: &str,
+        now: DateTime<Utc>,
+        quarantine: &Quarantine,
+    ) -> Result<(), RejectReason> {
+        let Some(limits) = self.classes.get(class) else {
+            return Ok(());
+        };
+        let device_id = device_id.to_lowercase();
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.prune(self.max_refill, now);
+        let scopes = [
+            (limits.device.as_ref(), None, "device"),
+            (limits.feature.as_ref(), Some(feature_name), "feature"),
+        ];
+        for (limit, feature_name, scope) in scopes {
+            let Some(limit) = limit else {
+                continue;
+            };
+            let bucket = buckets
+                .buckets
+                .entry((device_id.clone(), feature_name.map(str::to_string)))
+                .or_insert_with(|| Bucket {
+                    tokens: f64::from(limit.burst),
+                    updated_at: now,
+                    exceeded: 0,
+                });
+            bucket.refill(limit, now);
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                continue;
+            }
+            bucket.exceeded += 1;
+            match limit.action {
+                LimitAction::Drop => return Err(RejectReason::RateLimited(scope)),
+                LimitAction::Sample if bucket.exceeded.is_multiple_of(limit.sample_every) => {}
+                LimitAction::Sample => return Err(RejectReason::RateLimited(scope)),
+                LimitAction::Quarantine => {
+                    let until = now + TimeDelta::seconds(limit.quarantine_secs);
+                    quarantine.add(&device_id, until, REASON_RATE_LIMIT, now);
+                    return Err(RejectReason::Quarantined(device_id));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::RejectReason;
+    use crate::models::quarantine::Quarantine;
+    use crate::models::rate_limit::{DEFAULT_DEVICE_CLASS, RateLimiter};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+    use std::fs::{remove_file, write};
+
+    const DEVICE_ID: &str = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+
+    fn now() -> DateTime<Utc> {
+        "2025-12-25T10:30:00Z".parse().unwrap()
+    }
+
+    fn load_limits(test_name: &str, content: &str) -> RateLimiter {
+        let path = std::env::temp_dir().join(format!("{}.json", test_name));
+        write(&path, content).unwrap();
+        let limiter = RateLimiter::load(path.to_str().unwrap()).unwrap();
+        remove_file(&path).unwrap();
+        limiter
+    }
+
+    #[test]
+    fn check_rate_limit_drop() {
+        let limiter = load_limits(
+            "check_rate_limit_drop",
+            r#"{"classes": {
+                "default": {"device": {"burst": 3, "ratePerSec": 1}, "feature": {"burst": 2, "ratePerSec": 0.5}},
+                "gateway": {"device": {"burst": 100, "ratePerSec": 50}}
+            }}"#,
+        );
+        let quarantine = Quarantine::default();
+        let check = |feature_name: &str, class: &str, now: DateTime<Utc>| {
+            limiter.check(DEVICE_ID, feature_name, class, now, &quarantine)
+        };
+
+        // burst of the feature
+        assert_eq!(check("temperature", DEFAULT_DEVICE_CLASS, now()), Ok(()));
+        assert_eq!(check("temperature", DEFAULT_DEVICE_CLASS, now()), Ok(()));
+        assert_eq!(
+            check("temperature", DEFAULT_DEVICE_CLASS, now()),
+            Err(RejectReason::RateLimited("feature"))
+        );
+        // burst of the device, consumed also by the rejected message
+        assert_eq!(
+            check("humidity", DEFAULT_DEVICE_CLASS, now()),
+            Err(RejectReason::RateLimited("device"))
+        );
+        // sustained rate: 1 message per second for the device, 1 every 2 seconds for the feature
+        let later = now() + TimeDelta::seconds(1);
+        assert_eq!(check("humidity", DEFAULT_DEVICE_CLASS, later), Ok(()));
+        assert_eq!(
+            check("temperature", DEFAULT_DEVICE_CLASS, later),
+            Err(RejectReason::RateLimited("device"))
+        );
+        let later = now() + TimeDelta::seconds(3);
+        assert_eq!(check("temperature", DEFAULT_DEVICE_CLASS, later), Ok(()));
+
+        // other classes have their own limits, classes without limits are not limited
+        let other = "0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60";
+        for _ in 0..10 {
+            assert_eq!(
+                limiter.check(other, "temperature", "gateway", now(), &quarantine),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            limiter.check(other, "temperature", "unknown", now(), &quarantine),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn check_rate_limit_sample_and_quarantine() {
+        let limiter = load_limits(
+            "check_rate_limit_sample_and_quarantine",
+            r#"{"classes": {
+                "sampled": {"feature": {"burst": 1, "ratePerSec": 0.001, "action": "sample", "sampleEvery": 3}},
+                "default": {"device": {"burst": 1, "ratePerSec": 0.001, "action": "quarantine", "quarantineSecs": 60}}
+            }}"#,
+        );
+        let quarantine = Quarantine::default();
+
+        let sampled: Vec<bool> = (0..7)
+            .map(|_| {
+                limiter
+                    .check(DEVICE_ID, "temperature", "sampled", now(), &quarantine)
+                    .is_ok()
+            })
+            .collect();
+        assert_eq!(sampled, vec![true, false, false, true, false, false, true]);
+
+        let other = "0b1c6a4e-8f55-4f5e-9a2b-3a1d2c4e5f60";
+        assert_eq!(
+            limiter.check(other, "temperature", DEFAULT_DEVICE_CLASS, now(), &quarantine),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.check(other, "temperature", DEFAULT_DEVICE_CLASS, now(), &quarantine),
+            Err(RejectReason::Quarantined(other.to_string()))
+        );
+        // also with a new token, until the end of the quarantine
+        let later = now() + TimeDelta::seconds(59);
+        assert!(quarantine.is_quarantined(other, later));
+        assert!(!quarantine.is_quarantined(other, now() + TimeDelta::seconds(60)));
+    }
+
+    #[test]
+    fn wrong_load_rate_limits_file() {
+        let path = std::env::temp_dir().join("wrong_load_rate_limits_file.json");
+        write(
+            &path,
+            r#"{"classes": {"default": {"device": {"burst": 0, "ratePerSec": 1}}}}"#,
+        )
+        .unwrap();
+        let res = RateLimiter::load(path.to_str().unwrap());
+        remove_file(&path).unwrap();
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "invalid rate limit of device class default: burst must be at least 1"
+        );
+    }
+}
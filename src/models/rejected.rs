@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// envelope published to the rejects queue for messages that failed decoding or validation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedMessage {
+    pub topic: String,
+    pub feature: String,
+    // short name of the error, like `above_max` or `invalid_json`
+    pub kind: String,
+    pub reason: String,
+    pub payload: String,
+    pub received_at: DateTime<Utc>,
+}
+
+impl RejectedMessage {
+    pub fn new_as_json(topic: String, feature: String, kind: String, reason: String, payload: String) -> String {
+        let message = Self {
+            topic,
+            feature,
+            kind,
+            reason,
+            payload,
+            received_at: Utc::now(),
+        };
+        serde_json::to_string(&message).unwrap()
+    }
+}
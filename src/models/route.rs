@@ -0,0 +1,112 @@
+use std::fs::read_to_string;
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::errors::route_error::RouteError;
+use crate::models::schema_version::SchemaVersion;
+use crate::models::token_policy::ApiTokenPolicy;
+
+// additional queue that receives messages of some features,
+// with its own settings to migrate consumers one at a time
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Route {
+    pub name: String,
+    pub queue: String,
+    // features published to this route, all features if empty
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub schema_version: SchemaVersion,
+    // how apiToken is forwarded to this route, API_TOKEN_POLICY if not defined
+    #[serde(default)]
+    pub api_token_policy: Option<ApiTokenPolicy>,
+}
+
+#[derive(Deserialize)]
+struct RoutesFile {
+    routes: Vec<Route>,
+}
+
+impl Route {
+    pub fn matches(&self, feature_name: &str) -> bool {
+        self.features.is_empty() || self.features.iter().any(|name| name == feature_name)
+    }
+
+    // load routes from a JSON file with the format `{"routes": [...]}`
+    pub fn load(path: &str) -> Result<Vec<Self>, RouteError> {
+        info!(target: "app", "load - loading routes from file {}", path);
+        let content = read_to_string(path).map_err(|err| RouteError::Read(path.to_string(), err))?;
+        let file: RoutesFile =
+            serde_json::from_str(&content).map_err(|err| RouteError::Parse(path.to_string(), err))?;
+        for route in &file.routes {
+            if route.queue.is_empty() {
+                return Err(RouteError::InvalidRoute(
+                    route.name.clone(),
+                    String::from("queue is required"),
+                ));
+            }
+        }
+        Ok(file.routes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::route::Route;
+    use crate::models::schema_version::SchemaVersion;
+    use crate::models::token_policy::ApiTokenPolicy;
+    use pretty_assertions::assert_eq;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn ok_load_routes_file() {
+        let path = std::env::temp_dir().join("ok_load_routes_file.json");
+        write(
+            &path,
+            r#"{"routes": [
+                {"name": "legacy", "queue": "ks89_v1", "features": ["temperature"], "schemaVersion": 1},
+                {"name": "analytics", "queue": "analytics", "apiTokenPolicy": "drop"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let routes = Route::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].schema_version, SchemaVersion::V1);
+        assert!(routes[0].matches("temperature"));
+        assert!(!routes[0].matches("humidity"));
+        assert_eq!(routes[1].schema_version, SchemaVersion::LATEST);
+        assert!(routes[1].matches("humidity"));
+        assert_eq!(routes[0].api_token_policy, None);
+        assert_eq!(routes[1].api_token_policy, Some(ApiTokenPolicy::Drop));
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wrong_load_routes_file() {
+        let path = std::env::temp_dir().join("wrong_load_routes_file.json");
+
+        // route without queue
+        write(&path, r#"{"routes": [{"name": "legacy", "queue": ""}]}"#).unwrap();
+        let res = Route::load(path.to_str().unwrap());
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "invalid route legacy: queue is required"
+        );
+
+        // unsupported schema version
+        write(
+            &path,
+            r#"{"routes": [{"name": "legacy", "queue": "q", "schemaVersion": 9}]}"#,
+        )
+        .unwrap();
+        let res = Route::load(path.to_str().unwrap());
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            format!("cannot parse routes file {}", path.to_str().unwrap())
+        );
+        remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::message::Message;
+use crate::models::payload_trait::FeaturePayload;
+use crate::models::topic::Topic;
+
+// version of the Message envelope, serialized as `schemaVersion`.
+// Increment it for every change of fields in Message or Topic that breaks consumers,
+// keeping the previous layout available as a compatibility layer.
+// Optional fields omitted when empty, like `topic.segments`, don't need a new version.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(into = "u8", try_from = "u8")]
+pub enum SchemaVersion {
+    // original envelope: apiToken, deviceUuid, featureUuid, topic and payload
+    V1,
+    // adds schemaVersion, unit, originalUnit, timestamp and receivedAt
+    #[default]
+    V2,
+}
+
+impl SchemaVersion {
+    pub const LATEST: SchemaVersion = SchemaVersion::V2;
+}
+
+impl From<SchemaVersion> for u8 {
+    fn from(version: SchemaVersion) -> Self {
+        match version {
+            SchemaVersion::V1 => 1,
+            SchemaVersion::V2 => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for SchemaVersion {
+    type Error = String;
+
+    fn try_from(version: u8) -> Result<Self, Self::Error> {
+        match version {
+            1 => Ok(SchemaVersion::V1),
+            2 => Ok(SchemaVersion::V2),
+            _ => Err(format!("unsupported schema version {}", version)),
+        }
+    }
+}
+
+// Message with the layout of version 1
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageV1<'a> {
+    #[serde(skip_serializing_if = "is_empty")]
+    pub api_token: &'a str,
+    pub device_uuid: &'a str,
+    pub feature_uuid: &'a str,
+    pub topic: TopicV1<'a>,
+    pub payload: &'a FeaturePayload,
+}
+
+fn is_empty(value: &&str) -> bool {
+    value.is_empty()
+}
+
+// Topic with the layout of version 1
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicV1<'a> {
+    pub family: &'a str,
+    pub device_id: &'a str,
+    pub feature_name: &'a str,
+}
+
+impl<'a> From<&'a Topic> for TopicV1<'a> {
+    fn from(topic: &'a Topic) -> Self {
+        Self {
+            family: &topic.family,
+            device_id: &topic.device_id,
+            feature_name: &topic.feature_name,
+        }
+    }
+}
+
+impl<'a> From<&'a Message<FeaturePayload>> for MessageV1<'a> {
+    fn from(message: &'a Message<FeaturePayload>) -> Self {
+        Self {
+            api_token: &message.api_token,
+            device_uuid: &message.device_uuid,
+            feature_uuid: &message.feature_uuid,
+            topic: TopicV1::from(&message.topic),
+            payload: &message.payload,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::schema_version::SchemaVersion;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_schema_version() {
+        assert_eq!(serde_json::to_string(&SchemaVersion::LATEST).unwrap(), "2");
+        assert_eq!(serde_json::from_str::<SchemaVersion>("1").unwrap(), SchemaVersion::V1);
+        assert_eq!(
+            serde_json::from_str::<SchemaVersion>("3").err().unwrap().to_string(),
+            "unsupported schema version 3"
+        );
+    }
+}
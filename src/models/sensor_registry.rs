@@ -0,0 +1,579 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::read_to_string;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::errors::message_error::RejectReason;
+use crate::errors::registry_error::RegistryError;
+use crate::models::MULTI_FEATURE_NAME;
+use crate::models::payload_format::PayloadFormat;
+use crate::models::payload_trait::FeatureValue;
+use crate::models::topic::TopicTemplate;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Float,
+    Int,
+    Bool,
+    String,
+    Enum,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ValueType::Float => "float",
+            ValueType::Int => "int",
+            ValueType::Bool => "bool",
+            ValueType::String => "string",
+            ValueType::Enum => "enum",
+        };
+        fmt.write_str(name)
+    }
+}
+
+// linear conversion from a device unit to the canonical unit of a feature:
+// canonical = value * scale + offset
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UnitConversion {
+    pub from: String,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    // allowed values, required only for `ValueType::Enum`
+    #[serde(default)]
+    pub values: Vec<String>,
+    // allowed values for `ValueType::Int`, empty to allow all values
+    #[serde(default)]
+    pub allowed: Vec<i64>,
+    // conversions from other units to `unit`
+    #[serde(default)]
+    pub conversions: Vec<UnitConversion>,
+    // values sent by devices, compared ignoring case, and the value to use instead,
+    // like "ON" -> 1 or "detected" -> true. Booleans are mapped by "true" and "false".
+    #[serde(default)]
+    pub mappings: BTreeMap<String, FeatureValue>,
+}
+
+impl FeatureSpec {
+    pub fn new(name: &str, value_type: ValueType, unit: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            value_type,
+            unit: unit.map(|u| u.to_string()),
+            min: None,
+            max: None,
+            values: vec![],
+            allowed: vec![],
+            conversions: vec![],
+            mappings: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub fn with_allowed(mut self, allowed: &[i64]) -> Self {
+        self.allowed = allowed.to_vec();
+        self
+    }
+
+    pub fn with_conversion(mut self, from: &str, scale: f64, offset: f64) -> Self {
+        self.conversions.push(UnitConversion {
+            from: from.to_string(),
+            scale,
+            offset,
+        });
+        self
+    }
+
+    pub fn with_mapping(mut self, from: &[&str], to: FeatureValue) -> Self {
+        for key in from {
+            self.mappings.insert(key.to_string(), to.clone());
+        }
+        self
+    }
+
+    // replaces strings and booleans with their mapped value, if any
+    pub fn map_value(&self, value: FeatureValue) -> FeatureValue {
+        let key = match &value {
+            FeatureValue::String(text) => text.trim(),
+            FeatureValue::Bool(true) => "true",
+            FeatureValue::Bool(false) => "false",
+            _ => return value,
+        };
+        match self.mappings.iter().find(|(from, _)| from.eq_ignore_ascii_case(key)) {
+            Some((_, to)) => to.clone(),
+            None => value,
+        }
+    }
+
+    // converts a value expressed in `unit` to the canonical unit of this feature.
+    // Values without unit or already in the canonical unit are returned as is.
+    pub fn normalize(&self, value: FeatureValue, unit: Option<&str>) -> Result<FeatureValue, RejectReason> {
+        let Some(unit) = unit else {
+            return Ok(value);
+        };
+        if self.unit.as_deref() == Some(unit) {
+            return Ok(value);
+        }
+        let Some(conversion) = self.conversions.iter().find(|c| c.from == unit) else {
+            return Err(RejectReason::UnknownUnit(unit.to_string()));
+        };
+        let number = match value {
+            FeatureValue::Float(v) => v,
+            FeatureValue::Int(v) => v as f64,
+            _ => return Err(RejectReason::TypeMismatch(self.value_type)),
+        };
+        let converted = number * conversion.scale + conversion.offset;
+        match self.value_type {
+            ValueType::Int => Ok(FeatureValue::Int(converted.round() as i64)),
+            _ => Ok(FeatureValue::Float(converted)),
+        }
+    }
+
+    // returns the value converted to the type of this feature,
+    // or the reason why the value is not valid for this feature
+    pub fn check_value(&self, value: FeatureValue) -> Result<FeatureValue, RejectReason> {
+        let value = match (self.value_type, value) {
+            (ValueType::Float, FeatureValue::Float(v)) => FeatureValue::Float(v),
+            (ValueType::Float, FeatureValue::Int(v)) => FeatureValue::Float(v as f64),
+            (ValueType::Int, FeatureValue::Int(v)) => FeatureValue::Int(v),
+            (ValueType::Bool, FeatureValue::Bool(v)) => FeatureValue::Bool(v),
+            (ValueType::String, FeatureValue::String(v)) => FeatureValue::String(v),
+            (ValueType::Enum, FeatureValue::String(v)) => {
+                if !self.values.contains(&v) {
+                    return Err(RejectReason::NotAllowed(v));
+                }
+                FeatureValue::String(v)
+            }
+            _ => return Err(RejectReason::TypeMismatch(self.value_type)),
+        };
+        let number = match value {
+            FeatureValue::Float(v) => v,
+            FeatureValue::Int(v) => {
+                if !self.allowed.is_empty() && !self.allowed.contains(&v) {
+                    return Err(RejectReason::NotAllowed(v.to_string()));
+                }
+                v as f64
+            }
+            _ => return Ok(value),
+        };
+        if !number.is_finite() {
+            return Err(RejectReason::NotFinite);
+        }
+        if let Some(min) = self.min
+            && number < min
+        {
+            return Err(RejectReason::BelowMin(number, min));
+        }
+        if let Some(max) = self.max
+            && number > max
+        {
+            return Err(RejectReason::AboveMax(number, max));
+        }
+        Ok(value)
+    }
+
+    fn validate(&self) -> Result<(), RegistryError> {
+        if self.name.is_empty() || self.name.contains(['/', '+', '#']) {
+            return Err(RegistryError::InvalidFeature(
+                self.name.clone(),
+                String::from("name must be a valid MQTT topic level"),
+            ));
+        }
+        if self.name == MULTI_FEATURE_NAME {
+            return Err(RegistryError::InvalidFeature(
+                self.name.clone(),
+                String::from("name is reserved for composite topics"),
+            ));
+        }
+        // `sensors/<id>/json` would be read as a format suffix
+        if PayloadFormat::TOPIC_SUFFIXES.contains(&self.name.as_str()) {
+            return Err(RegistryError::InvalidFeature(
+                self.name.clone(),
+                String::from("name is reserved for payload formats"),
+            ));
+        }
+        if self.value_type == ValueType::Enum && self.values.is_empty() {
+            return Err(RegistryError::InvalidFeature(
+                self.name.clone(),
+                String::from("enum features require a list of values"),
+            ));
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max)
+            && min > max
+        {
+            return Err(RegistryError::InvalidFeature(
+                self.name.clone(),
+                String::from("min is greater than max"),
+            ));
+        }
+        if let Some((from, _)) = self
+            .mappings
+            .iter()
+            .find(|(_, to)| self.check_value((*to).clone()).is_err())
+        {
+            return Err(RegistryError::InvalidFeature(
+                self.name.clone(),
+                format!("mapping of {} is not a valid {} value", from, self.value_type),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryFile {
+    features: Vec<FeatureSpec>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SensorRegistry {
+    features: Vec<FeatureSpec>,
+}
+
+impl Default for SensorRegistry {
+    // built-in features, available also without a registry file
+    fn default() -> Self {
+        Self {
+            features: vec![
+                FeatureSpec::new("temperature", ValueType::Float, Some("°C"))
+                    .with_range(Some(-50.0), Some(100.0))
+                    .with_conversion("C", 1.0, 0.0)
+                    .with_conversion("°F", 5.0 / 9.0, -160.0 / 9.0)
+                    .with_conversion("F", 5.0 / 9.0, -160.0 / 9.0)
+                    .with_conversion("K", 1.0, -273.15),
+                FeatureSpec::new("humidity", ValueType::Float, Some("%")).with_range(Some(0.0), Some(100.0)),
+                // raw ADC values, like 0-1023, depend on the sensor and its circuit, so they have no built-in
+                // conversion: devices send them with unit "adc" and the scale is declared by overriding light
+                // in the registry file, see sensor_registry_template.json
+                FeatureSpec::new("light", ValueType::Float, Some("lx")).with_range(Some(0.0), None),
+                FeatureSpec::new("motion", ValueType::Int, None)
+                    .with_allowed(&[0, 1])
+                    .with_mapping(&["true", "on", "detected"], FeatureValue::Int(1))
+                    .with_mapping(&["false", "off", "clear"], FeatureValue::Int(0)),
+                FeatureSpec::new("airquality", ValueType::Int, None).with_range(Some(0.0), None),
+                FeatureSpec::new("airpressure", ValueType::Float, Some("hPa"))
+                    .with_range(Some(0.0), Some(1100.0))
+                    .with_conversion("Pa", 0.01, 0.0)
+                    .with_conversion("kPa", 10.0, 0.0)
+                    .with_conversion("mbar", 1.0, 0.0)
+                    .with_conversion("inHg", 33.8639, 0.0),
+                FeatureSpec::new("online", ValueType::Int, None)
+                    .with_allowed(&[0, 1])
+                    .with_mapping(&["true", "on", "online"], FeatureValue::Int(1))
+                    .with_mapping(&["false", "off", "offline"], FeatureValue::Int(0)),
+                FeatureSpec::new("co2", ValueType::Float, Some("ppm")).with_range(Some(0.0), Some(40000.0)),
+                FeatureSpec::new("voc", ValueType::Float, Some("ppb")).with_range(Some(0.0), None),
+                FeatureSpec::new("pm25", ValueType::Float, Some("µg/m³")).with_range(Some(0.0), Some(1000.0)),
+                FeatureSpec::new("pm10", ValueType::Float, Some("µg/m³")).with_range(Some(0.0), Some(1000.0)),
+                FeatureSpec::new("noise", ValueType::Float, Some("dB")).with_range(Some(0.0), Some(200.0)),
+                FeatureSpec::new("uvindex", ValueType::Float, None).with_range(Some(0.0), Some(20.0)),
+                FeatureSpec::new("battery", ValueType::Float, Some("%")).with_range(Some(0.0), Some(100.0)),
+                FeatureSpec::new("voltage", ValueType::Float, Some("V")).with_conversion("mV", 0.001, 0.0),
+                FeatureSpec::new("power", ValueType::Float, Some("W")).with_conversion("kW", 1000.0, 0.0),
+                FeatureSpec::new("energy", ValueType::Float, Some("kWh"))
+                    .with_range(Some(0.0), None)
+                    .with_conversion("Wh", 0.001, 0.0),
+                FeatureSpec::new("waterleak", ValueType::Int, None)
+                    .with_allowed(&[0, 1])
+                    .with_mapping(&["true", "on", "wet"], FeatureValue::Int(1))
+                    .with_mapping(&["false", "off", "dry"], FeatureValue::Int(0)),
+                FeatureSpec::new("doorcontact", ValueType::Int, None)
+                    .with_allowed(&[0, 1])
+                    .with_mapping(&["true", "on", "open"], FeatureValue::Int(1))
+                    .with_mapping(&["false", "off", "closed"], FeatureValue::Int(0)),
+            ],
+        }
+    }
+}
+
+impl SensorRegistry {
+    // load features from a JSON file on top of the built-in ones.
+    // A feature in the file with the same name of a built-in feature replaces it.
+    pub fn load(path: &str) -> Result<Self, RegistryError> {
+        info!(target: "app", "load - loading sensor registry from file {}", path);
+        let content = read_to_string(path).map_err(|err| RegistryError::Read(path.to_string(), err))?;
+        let file: RegistryFile =
+            serde_json::from_str(&content).map_err(|err| RegistryError::Parse(path.to_string(), err))?;
+        let mut registry = Self::default();
+        for feature in file.features {
+            feature.validate()?;
+            registry.insert(feature);
+        }
+        Ok(registry)
+    }
+
+    pub fn insert(&mut self, feature: FeatureSpec) {
+        debug!(target: "app", "insert - registering feature {}", &feature.name);
+        match self.features.iter_mut().find(|f| f.name == feature.name) {
+            Some(existing) => *existing = feature,
+            None => self.features.push(feature),
+        }
+    }
+
+    pub fn get(&self, feature_name: &str) -> Option<&FeatureSpec> {
+        self.features.iter().find(|f| f.name == feature_name)
+    }
+
+    pub fn features(&self) -> &[FeatureSpec] {
+        &self.features
+    }
+
+    // MQTT topics to subscribe to receive all registered features, also via composite topics
+    // and with a format suffix, like `sensors/+/temperature/cbor`
+    pub fn topics(&self, template: &TopicTemplate) -> Vec<String> {
+        let topics: Vec<String> = self
+            .features
+            .iter()
+            .map(|f| f.name.as_str())
+            .chain([MULTI_FEATURE_NAME])
+            .map(|name| template.subscription(name))
+            .collect();
+        let suffixed: Vec<String> = topics
+            .iter()
+            .flat_map(|topic| PayloadFormat::TOPIC_SUFFIXES.map(|suffix| format!("{}/{}", topic, suffix)))
+            .collect();
+        [topics, suffixed].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::RejectReason;
+    use crate::models::payload_trait::FeatureValue;
+    use crate::models::sensor_registry::{FeatureSpec, SensorRegistry, ValueType};
+    use crate::models::topic::TopicTemplate;
+    use pretty_assertions::assert_eq;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn ok_load_registry_file() {
+        let path = std::env::temp_dir().join("ok_load_registry_file.json");
+        write(
+            &path,
+            r#"{"features": [
+                {"name": "co2", "type": "float", "unit": "ppm", "min": 0, "max": 10000},
+                {"name": "state", "type": "enum", "values": ["open", "closed"]},
+                {"name": "light", "type": "int"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let registry = SensorRegistry::load(path.to_str().unwrap()).unwrap();
+        remove_file(&path).unwrap();
+
+        // built-in features are still available, overridden ones are replaced
+        assert_eq!(registry.features().len(), 20);
+        assert_eq!(registry.get("temperature").unwrap().value_type, ValueType::Float);
+        assert_eq!(registry.get("light").unwrap().value_type, ValueType::Int);
+        assert_eq!(registry.topics(&TopicTemplate::default()).len(), 84);
+        assert_eq!(registry.topics(&TopicTemplate::default())[19], "sensors/+/state");
+        assert_eq!(registry.topics(&TopicTemplate::default())[20], "sensors/+/multi");
+        assert_eq!(
+            registry.topics(&TopicTemplate::default())[21],
+            "sensors/+/temperature/json"
+        );
+        assert_eq!(
+            registry.topics(&TopicTemplate::default())[83],
+            "sensors/+/multi/msgpack"
+        );
+
+        let co2 = registry.get("co2").unwrap();
+        assert_eq!(co2.unit.as_deref(), Some("ppm"));
+        assert_eq!(co2.check_value(FeatureValue::Int(450)), Ok(FeatureValue::Float(450.0)));
+
+        let state = registry.get("state").unwrap();
+        assert_eq!(
+            state.check_value(FeatureValue::String("open".to_string())),
+            Ok(FeatureValue::String("open".to_string()))
+        );
+    }
+
+    #[test]
+    fn wrong_check_value() {
+        let registry = SensorRegistry::default();
+
+        let temperature = registry.get("temperature").unwrap();
+        assert_eq!(
+            temperature.check_value(FeatureValue::Int(-999)),
+            Err(RejectReason::BelowMin(-999.0, -50.0))
+        );
+        assert_eq!(
+            temperature.check_value(FeatureValue::Float(300.0)),
+            Err(RejectReason::AboveMax(300.0, 100.0))
+        );
+        assert_eq!(
+            temperature.check_value(FeatureValue::Float(f64::NAN)),
+            Err(RejectReason::NotFinite)
+        );
+        assert_eq!(
+            temperature.check_value(FeatureValue::Bool(true)),
+            Err(RejectReason::TypeMismatch(ValueType::Float))
+        );
+
+        let motion = registry.get("motion").unwrap();
+        assert_eq!(motion.check_value(FeatureValue::Int(1)), Ok(FeatureValue::Int(1)));
+        assert_eq!(
+            motion.check_value(FeatureValue::Int(2)),
+            Err(RejectReason::NotAllowed("2".to_string()))
+        );
+    }
+
+    #[test]
+    fn check_normalize() {
+        let registry = SensorRegistry::default();
+
+        let temperature = registry.get("temperature").unwrap();
+        assert_eq!(
+            temperature.normalize(FeatureValue::Float(21.5), None),
+            Ok(FeatureValue::Float(21.5))
+        );
+        assert_eq!(
+            temperature.normalize(FeatureValue::Float(21.5), Some("°C")),
+            Ok(FeatureValue::Float(21.5))
+        );
+        assert_eq!(
+            temperature.normalize(FeatureValue::Int(212), Some("°F")),
+            Ok(FeatureValue::Float(100.0))
+        );
+        assert_eq!(
+            temperature.normalize(FeatureValue::Float(21.5), Some("furlong")),
+            Err(RejectReason::UnknownUnit("furlong".to_string()))
+        );
+
+        let airpressure = registry.get("airpressure").unwrap();
+        assert_eq!(
+            airpressure.normalize(FeatureValue::Int(101325), Some("Pa")),
+            Ok(FeatureValue::Float(1013.25))
+        );
+
+        // raw ADC light values, configured via registry file
+        let light = FeatureSpec::new("light", ValueType::Int, Some("lx")).with_conversion("adc", 0.5, 0.0);
+        assert_eq!(
+            light.normalize(FeatureValue::Int(1023), Some("adc")),
+            Ok(FeatureValue::Int(512))
+        );
+    }
+
+    #[test]
+    fn ok_load_registry_template_adc_light() {
+        // built-in light has no conversion for raw ADC values
+        let light = SensorRegistry::default().get("light").unwrap().clone();
+        assert_eq!(
+            light.normalize(FeatureValue::Int(1023), Some("adc")),
+            Err(RejectReason::UnknownUnit("adc".to_string()))
+        );
+
+        // recipe of the template, for a sensor reaching 511.5 lx at 1023
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/sensor_registry_template.json");
+        let registry = SensorRegistry::load(path).unwrap();
+        let light = registry.get("light").unwrap();
+        let value = light.normalize(FeatureValue::Int(1023), Some("adc")).unwrap();
+        assert_eq!(light.check_value(value), Ok(FeatureValue::Float(511.5)));
+        assert_eq!(
+            light.normalize(FeatureValue::Float(300.0), Some("lx")),
+            Ok(FeatureValue::Float(300.0))
+        );
+    }
+
+    #[test]
+    fn check_map_value() {
+        let registry = SensorRegistry::default();
+        let text = |v: &str| FeatureValue::String(v.to_string());
+
+        let motion = registry.get("motion").unwrap();
+        assert_eq!(motion.map_value(text("ON")), FeatureValue::Int(1));
+        assert_eq!(motion.map_value(text("detected")), FeatureValue::Int(1));
+        assert_eq!(motion.map_value(FeatureValue::Bool(false)), FeatureValue::Int(0));
+        assert_eq!(motion.map_value(FeatureValue::Int(1)), FeatureValue::Int(1));
+        assert_eq!(motion.map_value(text("maybe")), text("maybe"));
+
+        let presence = FeatureSpec::new("presence", ValueType::Bool, None)
+            .with_mapping(&["detected"], FeatureValue::Bool(true))
+            .with_mapping(&["clear"], FeatureValue::Bool(false));
+        assert_eq!(presence.map_value(text("Detected")), FeatureValue::Bool(true));
+        assert_eq!(
+            presence.check_value(presence.map_value(text("clear"))),
+            Ok(FeatureValue::Bool(false))
+        );
+    }
+
+    #[test]
+    fn wrong_load_registry_file() {
+        let path = std::env::temp_dir().join("wrong_load_registry_file.json");
+
+        // enum without allowed values
+        write(&path, r#"{"features": [{"name": "state", "type": "enum"}]}"#).unwrap();
+        let res = SensorRegistry::load(path.to_str().unwrap());
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "invalid feature state in sensor registry: enum features require a list of values"
+        );
+
+        // unknown value type
+        write(&path, r#"{"features": [{"name": "co2", "type": "double"}]}"#).unwrap();
+        let res = SensorRegistry::load(path.to_str().unwrap());
+        assert!(
+            res.err()
+                .unwrap()
+                .to_string()
+                .starts_with("cannot parse sensor registry file")
+        );
+
+        // mapping to a value of the wrong type
+        write(
+            &path,
+            r#"{"features": [{"name": "presence", "type": "bool", "mappings": {"ON": 1}}]}"#,
+        )
+        .unwrap();
+        let res = SensorRegistry::load(path.to_str().unwrap());
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "invalid feature presence in sensor registry: mapping of ON is not a valid bool value"
+        );
+
+        // reserved names
+        for name in ["multi", "json", "cbor", "msgpack"] {
+            write(
+                &path,
+                format!(r#"{{"features": [{{"name": "{}", "type": "int"}}]}}"#, name),
+            )
+            .unwrap();
+            let res = SensorRegistry::load(path.to_str().unwrap());
+            assert!(res.err().unwrap().to_string().starts_with(&format!(
+                "invalid feature {} in sensor registry: name is reserved",
+                name
+            )));
+        }
+
+        remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::errors::message_error::RejectReason;
+use crate::models::device_registry::ReloadableDeviceRegistry;
+use crate::models::notification::Notification;
+use crate::models::payload_trait::FeaturePayload;
+
+// nonces are pruned when the cache reaches this size, at least
+const MIN_NONCES_PRUNE_SIZE: usize = 1024;
+
+// if readings must be signed with the signing secret of their device
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureMode {
+    // verify signed readings and accept unsigned ones
+    #[default]
+    Optional,
+    // drop unsigned readings
+    Strict,
+}
+
+// verification of HMAC-SHA256 signatures of readings, with replay protection.
+// The device signs the text returned by `signed_content` and sends the hex signature in `signature`,
+// with a unique `nonce` and a `timestamp` within `max_age` of the receive time.
+// Clones share the same used nonces.
+#[derive(Debug, Clone)]
+pub struct SignaturePolicy {
+    pub mode: SignatureMode,
+    pub max_age: TimeDelta,
+    nonces: Arc<Mutex<NonceCache>>,
+}
+
+impl Default for SignaturePolicy {
+    fn default() -> Self {
+        Self::new(SignatureMode::default(), TimeDelta::minutes(5))
+    }
+}
+
+// text signed by the device: deviceUuid, featureUuid, nonce, timestamp as epoch milliseconds
+// and the value as JSON, separated by new lines.
+// For example "246e3256-...\n41cb3f47-...\nnonce-1\n1766658600000\n21.5"
+pub fn signed_content(
+    device_uuid: &str,
+    feature_uuid: &str,
+    nonce: &str,
+    timestamp: DateTime<Utc>,
+    payload: &FeaturePayload,
+) -> String {
+    let value = serde_json::to_string(&payload.value).unwrap_or_default();
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        device_uuid,
+        feature_uuid,
+        nonce,
+        timestamp.timestamp_millis(),
+        value
+    )
+}
+
+impl SignaturePolicy {
+    pub fn new(mode: SignatureMode, max_age: TimeDelta) -> Self {
+        Self {
+            mode,
+            max_age,
+            nonces: Arc::new(Mutex::new(NonceCache::default())),
+        }
+    }
+
+    // returns true if the reading is signed and valid, false if it's unsigned and accepted,
+    // or the reason why the reading must be rejected.
+    // Without a device registry there are no signing secrets, so signatures are not verified.
+    pub fn verify(
+        &self,
+        val: &Notification<'_, FeaturePayload>,
+        received_at: DateTime<Utc>,
+        devices: Option<&ReloadableDeviceRegistry>,
+    ) -> Result<bool, RejectReason> {
+        let Some(signature) = val.signature.as_deref() else {
+            return match self.mode {
+                SignatureMode::Optional => Ok(false),
+                SignatureMode::Strict => Err(RejectReason::Unsigned(val.device_uuid.to_string())),
+            };
+        };
+        let Some(devices) = devices else {
+            return Ok(false);
+        };
+        let invalid = || RejectReason::InvalidSignature(val.device_uuid.to_string());
+        // both are part of the signed content
+        let (Some(nonce), Some(timestamp)) = (val.nonce.as_deref(), val.timestamp) else {
+            return Err(invalid());
+        };
+        let age = received_at - timestamp;
+        if age.abs() > self.max_age {
+            return Err(RejectReason::SignatureExpired(age.num_seconds()));
+        }
+        let content = signed_content(&val.device_uuid, &val.feature_uuid, nonce, timestamp, &val.payload);
+        devices.verify_signature(&val.device_uuid, &content, signature)?;
+        // nonces are recorded only for valid signatures, otherwise anyone could burn them.
+        // After `max_age` the timestamp is rejected anyway, so the nonce can be forgotten.
+        let key = format!("{}/{}", val.device_uuid.to_lowercase(), nonce);
+        if !self
+            .nonces
+            .lock()
+            .unwrap()
+            .insert(key, timestamp + self.max_age, received_at)
+        {
+            warn!(target: "app", "verify - nonce {} of device {} was already used", nonce, &val.device_uuid);
+            return Err(RejectReason::ReplayedNonce(nonce.to_string()));
+        }
+        Ok(true)
+    }
+}
+
+// nonces of valid signatures, with their expiration time
+#[derive(Debug, Default)]
+struct NonceCache {
+    expirations: HashMap<String, DateTime<Utc>>,
+    prune_size: usize,
+}
+
+impl NonceCache {
+    // returns false if the nonce was already used and is not expired
+    fn insert(&mut self, key: String, expiration: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        if self.expirations.len() >= self.prune_size.max(MIN_NONCES_PRUNE_SIZE) {
+            self.expirations.retain(|_, expiration| *expiration >= now);
+            self.prune_size = self.expirations.len() * 2;
+        }
+        match self.expirations.get(&key) {
+            Some(used) if *used >= now => false,
+            _ => {
+                self.expirations.insert(key, expiration);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::RejectReason;
+    use crate::models::device_registry::ReloadableDeviceRegistry;
+    use crate::models::notification::Notification;
+    use crate::models::payload_trait::{FeaturePayload, FeatureValue};
+    use crate::models::signature::{SignatureMode, SignaturePolicy, signed_content};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use hmac::{Hmac, Mac};
+    use pretty_assertions::assert_eq;
+    use sha2::Sha256;
+    use std::borrow::Cow;
+    use std::fs::{remove_file, write};
+
+    const DEVICE_UUID: &str = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+    const FEATURE_UUID: &str = "41cb3f47-894c-45e9-90d9-a4d4de903896";
+    const SIGNING_SECRET: &str = "device-s3cret";
+
+    fn received_at() -> DateTime<Utc> {
+        "2025-12-25T10:30:00Z".parse().unwrap()
+    }
+
+    fn load_devices(test_name: &str) -> ReloadableDeviceRegistry {
+        let path = std::env::temp_dir().join(format!("{}.json", test_name));
+        write(
+            &path,
+            format!(
+                r#"{{"devices": [{{"deviceUuid": "{}", "apiTokenHash": "896c8db249671c4c68dff319d25dd859c4a0bde3efd1d201e66f61e2c9f9caa3", "features": ["temperature"], "signingSecret": "{}"}}]}}"#,
+                DEVICE_UUID, SIGNING_SECRET
+            ),
+        )
+        .unwrap();
+        let devices = ReloadableDeviceRegistry::load(path.to_str().unwrap()).unwrap();
+        remove_file(&path).unwrap();
+        devices
+    }
+
+    fn notification(nonce: &str, timestamp: DateTime<Utc>, secret: &str) -> Notification<'static, FeaturePayload> {
+        let payload = FeaturePayload {
+            value: FeatureValue::Float(21.5),
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(signed_content(DEVICE_UUID, FEATURE_UUID, nonce, timestamp, &payload).as_bytes());
+        Notification {
+            device_uuid: Cow::Borrowed(DEVICE_UUID),
+            feature_uuid: Cow::Borrowed(FEATURE_UUID),
+            api_token: Cow::Borrowed("473a4861-632b-4915-b01e-cf1d418966c6"),
+            payload,
+            unit: None,
+            timestamp: Some(timestamp),
+            signature: Some(hex::encode(mac.finalize().into_bytes())),
+            nonce: Some(nonce.to_string()),
+        }
+    }
+
+    #[test]
+    fn check_signed_content() {
+        let payload = FeaturePayload {
+            value: FeatureValue::Float(21.5),
+        };
+        assert_eq!(
+            signed_content(DEVICE_UUID, FEATURE_UUID, "nonce-1", received_at(), &payload),
+            format!("{}\n{}\nnonce-1\n1766658600000\n21.5", DEVICE_UUID, FEATURE_UUID)
+        );
+    }
+
+    #[test]
+    fn check_verify_signature() {
+        let devices = load_devices("check_verify_signature");
+        let policy = SignaturePolicy::new(SignatureMode::Optional, TimeDelta::minutes(5));
+        let signed = notification("nonce-1", received_at() - TimeDelta::seconds(30), SIGNING_SECRET);
+
+        assert_eq!(policy.verify(&signed, received_at(), Some(&devices)), Ok(true));
+        // replayed message
+        assert_eq!(
+            policy.verify(&signed, received_at(), Some(&devices)),
+            Err(RejectReason::ReplayedNonce("nonce-1".to_string()))
+        );
+        // the same nonce of another message, shared by clones of the policy
+        let replayed = notification("nonce-1", received_at(), SIGNING_SECRET);
+        assert_eq!(
+            policy.clone().verify(&replayed, received_at(), Some(&devices)),
+            Err(RejectReason::ReplayedNonce("nonce-1".to_string()))
+        );
+
+        // tampered value
+        let tampered = Notification {
+            payload: FeaturePayload {
+                value: FeatureValue::Float(30.0),
+            },
+            ..notification("nonce-2", received_at(), SIGNING_SECRET)
+        };
+        assert_eq!(
+            policy.verify(&tampered, received_at(), Some(&devices)),
+            Err(RejectReason::InvalidSignature(DEVICE_UUID.to_string()))
+        );
+        // wrong secret
+        let forged = notification("nonce-3", received_at(), "guessed");
+        assert_eq!(
+            policy.verify(&forged, received_at(), Some(&devices)),
+            Err(RejectReason::InvalidSignature(DEVICE_UUID.to_string()))
+        );
+        // outside the timestamp window
+        let old = notification("nonce-4", received_at() - TimeDelta::minutes(10), SIGNING_SECRET);
+        assert_eq!(
+            policy.verify(&old, received_at(), Some(&devices)),
+            Err(RejectReason::SignatureExpired(600))
+        );
+        // signed without nonce
+        let without_nonce = Notification {
+            nonce: None,
+            ..notification("nonce-5", received_at(), SIGNING_SECRET)
+        };
+        assert_eq!(
+            policy.verify(&without_nonce, received_at(), Some(&devices)),
+            Err(RejectReason::InvalidSignature(DEVICE_UUID.to_string()))
+        );
+    }
+
+    #[test]
+    fn check_unsigned_readings() {
+        let devices = load_devices("check_unsigned_readings");
+        let unsigned = Notification {
+            signature: None,
+            nonce: None,
+            ..notification("nonce-1", received_at(), SIGNING_SECRET)
+        };
+        let optional = SignaturePolicy::new(SignatureMode::Optional, TimeDelta::minutes(5));
+        assert_eq!(optional.verify(&unsigned, received_at(), Some(&devices)), Ok(false));
+        let strict = SignaturePolicy::new(SignatureMode::Strict, TimeDelta::minutes(5));
+        assert_eq!(
+            strict.verify(&unsigned, received_at(), Some(&devices)),
+            Err(RejectReason::Unsigned(DEVICE_UUID.to_string()))
+        );
+    }
+}
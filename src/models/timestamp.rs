@@ -0,0 +1,171 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::warn;
+
+use crate::errors::message_error::RejectReason;
+
+// epoch values greater than this are considered milliseconds.
+// 1e11 seconds is year 5138, while 1e11 milliseconds is year 1973.
+const EPOCH_MILLIS_THRESHOLD: f64 = 1e11;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTimestamp {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+// deserialize an optional device timestamp as epoch seconds, epoch milliseconds or RFC 3339 string
+pub fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw: Option<RawTimestamp> = Option::deserialize(deserializer)?;
+    let timestamp = match raw {
+        None => return Ok(None),
+        Some(RawTimestamp::Int(epoch)) => from_epoch(epoch as f64),
+        Some(RawTimestamp::Float(epoch)) => from_epoch(epoch),
+        Some(RawTimestamp::Text(text)) => DateTime::parse_from_rfc3339(&text).ok().map(|ts| ts.to_utc()),
+    };
+    match timestamp {
+        Some(ts) => Ok(Some(ts)),
+        None => Err(serde::de::Error::custom(
+            "timestamp must be epoch seconds, epoch milliseconds or RFC 3339",
+        )),
+    }
+}
+
+// like `deserialize_timestamp`, but the timestamp is mandatory
+pub fn deserialize_required_timestamp<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_timestamp(deserializer)?.ok_or_else(|| serde::de::Error::custom("timestamp is required"))
+}
+
+fn from_epoch(epoch: f64) -> Option<DateTime<Utc>> {
+    if !epoch.is_finite() || epoch < 0.0 {
+        return None;
+    }
+    let millis = if epoch > EPOCH_MILLIS_THRESHOLD {
+        epoch
+    } else {
+        epoch * 1000.0
+    };
+    DateTime::from_timestamp_millis(millis as i64)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SkewAction {
+    // forward the device timestamp as is
+    #[default]
+    Accept,
+    // replace the device timestamp with the time of reception
+    Replace,
+    // drop the reading
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampPolicy {
+    pub action: SkewAction,
+    // maximum allowed difference between the device timestamp and the time of reception.
+    // Readings in the past are always allowed, because they could have waited in a queue.
+    pub max_skew: TimeDelta,
+}
+
+impl Default for TimestampPolicy {
+    fn default() -> Self {
+        Self {
+            action: SkewAction::Accept,
+            max_skew: TimeDelta::seconds(60),
+        }
+    }
+}
+
+impl TimestampPolicy {
+    // returns the timestamp to forward, or the reason why the reading must be rejected
+    pub fn apply(
+        &self,
+        timestamp: Option<DateTime<Utc>>,
+        received_at: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, RejectReason> {
+        let Some(ts) = timestamp else {
+            return Ok(None);
+        };
+        let skew = ts - received_at;
+        if skew <= self.max_skew {
+            return Ok(Some(ts));
+        }
+        warn!(target: "app", "apply - device timestamp {} is {} seconds ahead of the producer clock", ts, skew.num_seconds());
+        match self.action {
+            SkewAction::Accept => Ok(Some(ts)),
+            SkewAction::Replace => Ok(Some(received_at)),
+            SkewAction::Reject => Err(RejectReason::TimestampSkew(skew.num_seconds())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::message_error::RejectReason;
+    use crate::models::timestamp::{SkewAction, TimestampPolicy, deserialize_timestamp};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct WithTimestamp {
+        #[serde(default, deserialize_with = "deserialize_timestamp")]
+        timestamp: Option<DateTime<Utc>>,
+    }
+
+    fn parse(json: &str) -> Result<Option<DateTime<Utc>>, serde_json::Error> {
+        serde_json::from_str::<WithTimestamp>(json).map(|v| v.timestamp)
+    }
+
+    #[test]
+    fn ok_deserialize_timestamp() {
+        let expected: DateTime<Utc> = "2025-12-25T10:30:00Z".parse().unwrap();
+        assert_eq!(parse(r#"{"timestamp": 1766658600}"#).unwrap(), Some(expected));
+        assert_eq!(parse(r#"{"timestamp": 1766658600000}"#).unwrap(), Some(expected));
+        assert_eq!(parse(r#"{"timestamp": 1766658600.0}"#).unwrap(), Some(expected));
+        assert_eq!(
+            parse(r#"{"timestamp": "2025-12-25T11:30:00+01:00"}"#).unwrap(),
+            Some(expected)
+        );
+        assert_eq!(parse(r#"{"timestamp": null}"#).unwrap(), None);
+        assert_eq!(parse(r#"{}"#).unwrap(), None);
+    }
+
+    #[test]
+    fn wrong_deserialize_timestamp() {
+        assert!(parse(r#"{"timestamp": "yesterday"}"#).is_err());
+        assert!(parse(r#"{"timestamp": -1}"#).is_err());
+        assert!(parse(r#"{"timestamp": true}"#).is_err());
+    }
+
+    #[test]
+    fn check_timestamp_policy() {
+        let received_at: DateTime<Utc> = "2025-12-25T10:30:00Z".parse().unwrap();
+        let past = received_at - TimeDelta::hours(1);
+        let future = received_at + TimeDelta::minutes(5);
+        let mut policy = TimestampPolicy::default();
+
+        assert_eq!(policy.apply(None, received_at), Ok(None));
+        assert_eq!(policy.apply(Some(past), received_at), Ok(Some(past)));
+        assert_eq!(policy.apply(Some(future), received_at), Ok(Some(future)));
+
+        policy.action = SkewAction::Replace;
+        assert_eq!(policy.apply(Some(past), received_at), Ok(Some(past)));
+        assert_eq!(policy.apply(Some(future), received_at), Ok(Some(received_at)));
+
+        policy.action = SkewAction::Reject;
+        assert_eq!(
+            policy.apply(Some(future), received_at),
+            Err(RejectReason::TimestampSkew(300))
+        );
+    }
+}
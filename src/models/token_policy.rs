@@ -0,0 +1,107 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+// name of the AMQP header with the API token, when the token is not in the message body
+pub const API_TOKEN_HEADER: &str = "apiToken";
+
+// how the apiToken of a reading is forwarded to a queue,
+// so consumers that don't authenticate devices never see the plain token
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiTokenPolicy {
+    // forward the token unchanged
+    #[default]
+    Forward,
+    // remove the token from the message
+    Drop,
+    // replace the token with its hex HMAC-SHA256, keyed with API_TOKEN_HASH_KEY
+    Hash,
+    // remove the token from the message and send it in the AMQP header `apiToken`
+    Header,
+}
+
+// apiToken to publish: the value for the message body, empty to omit it, and the AMQP header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardedToken {
+    pub body: String,
+    pub header: Option<String>,
+}
+
+impl ApiTokenPolicy {
+    pub fn needs_hash_key(&self) -> bool {
+        *self == ApiTokenPolicy::Hash
+    }
+
+    pub fn apply(&self, api_token: &str, hash_key: &[u8]) -> ForwardedToken {
+        match self {
+            ApiTokenPolicy::Forward => ForwardedToken {
+                body: api_token.to_string(),
+                header: None,
+            },
+            ApiTokenPolicy::Drop => ForwardedToken {
+                body: String::new(),
+                header: None,
+            },
+            ApiTokenPolicy::Hash => ForwardedToken {
+                body: keyed_hash(api_token, hash_key),
+                header: None,
+            },
+            ApiTokenPolicy::Header => ForwardedToken {
+                body: String::new(),
+                header: Some(api_token.to_string()),
+            },
+        }
+    }
+}
+
+// the same token always has the same hash, so consumers can still group readings by token
+fn keyed_hash(api_token: &str, hash_key: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hash_key).expect("HMAC accepts keys of any length");
+    mac.update(api_token.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::token_policy::{ApiTokenPolicy, ForwardedToken};
+    use pretty_assertions::assert_eq;
+
+    const API_TOKEN: &str = "473a4861-632b-4915-b01e-cf1d418966c6";
+
+    #[test]
+    fn check_api_token_policy() {
+        let key = b"hash-key";
+        assert_eq!(
+            ApiTokenPolicy::Forward.apply(API_TOKEN, key),
+            ForwardedToken {
+                body: API_TOKEN.to_string(),
+                header: None
+            }
+        );
+        assert_eq!(
+            ApiTokenPolicy::Drop.apply(API_TOKEN, key),
+            ForwardedToken {
+                body: String::new(),
+                header: None
+            }
+        );
+        assert_eq!(
+            ApiTokenPolicy::Header.apply(API_TOKEN, key),
+            ForwardedToken {
+                body: String::new(),
+                header: Some(API_TOKEN.to_string())
+            }
+        );
+
+        // printf '%s' "473a4861-632b-4915-b01e-cf1d418966c6" | openssl dgst -sha256 -hmac "hash-key"
+        let hashed = ApiTokenPolicy::Hash.apply(API_TOKEN, key);
+        assert_eq!(
+            hashed.body,
+            "fc33a674e2a21827e69abdf0c20f4239937c9587b19475ea328400c353e20d18"
+        );
+        assert_eq!(hashed.header, None);
+        assert_eq!(ApiTokenPolicy::Hash.apply(API_TOKEN, key), hashed);
+        assert_ne!(ApiTokenPolicy::Hash.apply(API_TOKEN, b"other-key"), hashed);
+    }
+}
@@ -1,31 +1,179 @@
+use std::collections::BTreeMap;
 use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::errors::topic_error::TopicError;
+
+const FAMILY: &str = "family";
+const DEVICE_ID: &str = "deviceId";
+const FEATURE_NAME: &str = "featureName";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Topic {
     pub family: String,
     pub device_id: String,
     pub feature_name: String,
+    // additional named segments of the topic template, like `homeId` or `room`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub segments: BTreeMap<String, String>,
+    // levels of the received topic before the feature name, to display it as received
+    #[serde(skip)]
+    pub(crate) prefix: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(String),
+}
+
+// structure of MQTT topics, with literal segments and named segments between braces,
+// like `sensors/{deviceId}/{featureName}` or `home/{homeId}/{room}/{deviceId}/{featureName}`.
+// `deviceId` and `featureName` are required, `featureName` must be the last segment.
+// `family` is the value of the `{family}` segment, or the first segment of the topic.
+#[derive(Debug, Clone)]
+pub struct TopicTemplate {
+    template: String,
+    segments: Vec<Segment>,
+    // reject topics whose device id is not a UUID
+    validate_device_uuid: bool,
+}
+
+impl TopicTemplate {
+    pub const DEFAULT: &'static str = "sensors/{deviceId}/{featureName}";
+
+    pub fn new(template: &str) -> Result<Self, TopicError> {
+        let invalid = |reason: &str| TopicError::InvalidTemplate(template.to_string(), reason.to_string());
+        let mut segments = Vec::new();
+        for item in template.split('/') {
+            let segment = match item.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) if !name.is_empty() => Segment::Field(name.to_string()),
+                _ => Segment::Literal(item.to_string()),
+            };
+            match &segment {
+                Segment::Literal(literal) if literal.is_empty() || literal.contains(['+', '#', '{', '}']) => {
+                    return Err(invalid("segments must be non empty names or {field} placeholders"));
+                }
+                Segment::Field(_) if segments.contains(&segment) => {
+                    return Err(invalid("placeholders must be unique"));
+                }
+                _ => segments.push(segment),
+            }
+        }
+        if !segments.contains(&Segment::Field(DEVICE_ID.to_string())) {
+            return Err(invalid("{deviceId} is required"));
+        }
+        if segments.last() != Some(&Segment::Field(FEATURE_NAME.to_string())) {
+            return Err(invalid("{featureName} is required as last segment"));
+        }
+        Ok(Self {
+            template: template.to_string(),
+            segments,
+            validate_device_uuid: false,
+        })
+    }
+
+    pub fn with_device_uuid_validation(mut self, validate_device_uuid: bool) -> Self {
+        self.validate_device_uuid = validate_device_uuid;
+        self
+    }
+
+    // MQTT subscription for a feature, with a single level wildcard for every other placeholder
+    pub fn subscription(&self, feature_name: &str) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.as_str(),
+                Segment::Field(name) if name == FEATURE_NAME => feature_name,
+                Segment::Field(_) => "+",
+            })
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+}
+
+impl Default for TopicTemplate {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT).unwrap()
+    }
+}
+
+impl fmt::Display for TopicTemplate {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.template.as_str())
+    }
 }
 
 impl Topic {
+    // split a trusted topic with the structure `family/deviceId/.../featureName`.
+    // Use `Topic::parse` for topics received via MQTT.
     pub fn new(topic: &str) -> Self {
         let items: Vec<&str> = topic.split('/').collect();
         Self {
-            family: items.first().unwrap().to_string(),
-            device_id: items.get(1).unwrap().to_string(),
-            feature_name: items.last().unwrap().to_string(),
+            family: items.first().unwrap_or(&"").to_string(),
+            device_id: items.get(1).unwrap_or(&"").to_string(),
+            feature_name: items.last().unwrap_or(&"").to_string(),
+            segments: BTreeMap::new(),
+            prefix: topic.rsplit_once('/').map_or("", |(prefix, _)| prefix).to_string(),
         }
     }
+
+    pub fn parse(topic: &str, template: &TopicTemplate) -> Result<Self, TopicError> {
+        let mismatch = || TopicError::Mismatch(topic.to_string(), template.to_string());
+        let items: Vec<&str> = topic.split('/').collect();
+        if items.len() != template.segments.len() {
+            return Err(mismatch());
+        }
+        let mut fields: BTreeMap<String, String> = BTreeMap::new();
+        for (item, segment) in items.iter().zip(&template.segments) {
+            match segment {
+                Segment::Literal(literal) if literal != item => return Err(mismatch()),
+                Segment::Literal(_) => {}
+                Segment::Field(_) if item.is_empty() => return Err(mismatch()),
+                Segment::Field(name) => {
+                    fields.insert(name.clone(), item.to_string());
+                }
+            }
+        }
+        let family = fields.remove(FAMILY).unwrap_or_else(|| items[0].to_string());
+        let device_id = fields.remove(DEVICE_ID).unwrap_or_default();
+        let feature_name = fields.remove(FEATURE_NAME).unwrap_or_default();
+        if template.validate_device_uuid && !is_uuid(&device_id) {
+            return Err(TopicError::InvalidDeviceId(device_id));
+        }
+        Ok(Self {
+            family,
+            device_id,
+            feature_name,
+            segments: fields,
+            // featureName is the last segment of every template
+            prefix: items[..items.len() - 1].join("/"),
+        })
+    }
 }
 
+// hyphenated UUID, like 246e3256-f0dd-4fcb-82c5-ee20c2267eeb
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+// the topic as received, with the feature name of the reading for multi readings,
+// or `family/deviceId/featureName` for deserialized topics
 impl fmt::Display for Topic {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str(self.family.as_str())?;
-        fmt.write_str("/")?;
-        fmt.write_str(self.device_id.as_str())?;
+        if self.prefix.is_empty() {
+            fmt.write_str(self.family.as_str())?;
+            fmt.write_str("/")?;
+            fmt.write_str(self.device_id.as_str())?;
+        } else {
+            fmt.write_str(self.prefix.as_str())?;
+        }
         fmt.write_str("/")?;
         fmt.write_str(self.feature_name.as_str())?;
         Ok(())
@@ -34,7 +182,7 @@ impl fmt::Display for Topic {
 
 #[cfg(test)]
 mod tests {
-    use crate::models::topic::Topic;
+    use crate::models::topic::{Topic, TopicTemplate};
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -46,5 +194,98 @@ mod tests {
         let topic: Topic = Topic::new(format!("sensors/{}/{}", uuid, sensor_type).as_str());
         let expected = topic.to_string();
         assert_eq!(format!("sensors/{}/{}", uuid, sensor_type), expected);
+
+        let template = TopicTemplate::new("home/{homeId}/{room}/{deviceId}/{featureName}").unwrap();
+        let topic = Topic::parse("home/h1/kitchen/abc/humidity", &template).unwrap();
+        assert_eq!(topic.to_string(), "home/h1/kitchen/abc/humidity");
+        let topic: Topic = serde_json::from_str(&serde_json::to_string(&topic).unwrap()).unwrap();
+        assert_eq!(topic.to_string(), "home/abc/humidity");
+    }
+
+    #[test]
+    fn ok_parse_topic() {
+        let uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+
+        let topic = Topic::parse(
+            format!("sensors/{}/temperature", uuid).as_str(),
+            &TopicTemplate::default(),
+        )
+        .unwrap();
+        assert_eq!(topic.family, "sensors");
+        assert_eq!(topic.device_id, uuid);
+        assert_eq!(topic.feature_name, "temperature");
+        assert!(topic.segments.is_empty());
+
+        let template = TopicTemplate::new("home/{homeId}/{room}/{deviceId}/{featureName}").unwrap();
+        let topic = Topic::parse("home/h1/kitchen/abc/humidity", &template).unwrap();
+        assert_eq!(topic.family, "home");
+        assert_eq!(topic.device_id, "abc");
+        assert_eq!(topic.feature_name, "humidity");
+        assert_eq!(topic.segments.get("homeId").unwrap(), "h1");
+        assert_eq!(topic.segments.get("room").unwrap(), "kitchen");
+        assert_eq!(
+            serde_json::to_string(&topic).unwrap(),
+            r#"{"family":"home","deviceId":"abc","featureName":"humidity","segments":{"homeId":"h1","room":"kitchen"}}"#
+        );
+
+        let template = TopicTemplate::new("{family}/{deviceId}/{featureName}").unwrap();
+        let topic = Topic::parse("lights/abc/online", &template).unwrap();
+        assert_eq!(topic.family, "lights");
+        assert_eq!(template.subscription("online"), "+/+/online");
+    }
+
+    #[test]
+    fn wrong_parse_topic() {
+        let template = TopicTemplate::default();
+        for topic in [
+            "",
+            "sensors",
+            "sensors/abc/x/y/temperature",
+            "devices/abc/temperature",
+            "sensors//temperature",
+        ] {
+            assert_eq!(
+                Topic::parse(topic, &template).err().unwrap().to_string(),
+                format!(
+                    "topic {} doesn't match template sensors/{{deviceId}}/{{featureName}}",
+                    topic
+                )
+            );
+        }
+
+        let template = TopicTemplate::default().with_device_uuid_validation(true);
+        assert!(Topic::parse("sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature", &template).is_ok());
+        assert_eq!(
+            Topic::parse("sensors/246e3256-f0dd-4fcb-82c5/temperature", &template)
+                .err()
+                .unwrap()
+                .to_string(),
+            "device id 246e3256-f0dd-4fcb-82c5 is not a valid UUID"
+        );
+    }
+
+    #[test]
+    fn wrong_topic_template() {
+        for (template, reason) in [
+            ("sensors/{featureName}", "{deviceId} is required"),
+            (
+                "sensors/{featureName}/{deviceId}",
+                "{featureName} is required as last segment",
+            ),
+            (
+                "sensors//{deviceId}/{featureName}",
+                "segments must be non empty names or {field} placeholders",
+            ),
+            (
+                "sensors/+/{deviceId}/{featureName}",
+                "segments must be non empty names or {field} placeholders",
+            ),
+            ("{room}/{room}/{deviceId}/{featureName}", "placeholders must be unique"),
+        ] {
+            assert_eq!(
+                TopicTemplate::new(template).err().unwrap().to_string(),
+                format!("invalid topic template {}: {}", template, reason)
+            );
+        }
     }
 }
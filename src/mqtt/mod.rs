@@ -1,10 +1,17 @@
 use std::string::String;
 
-use paho_mqtt::Message;
+use chrono::{DateTime, Utc};
+use paho_mqtt::{Message, PropertyCode};
 use tracing::{debug, error};
 
-use crate::models::get_msg_byte;
+use crate::errors::message_error::MessageError;
+use crate::metrics::{Metrics, OTHER_DEVICE_LABEL};
+use crate::models::message::Envelope;
+use crate::models::payload_format::PayloadFormat;
+use crate::models::processing_config::ProcessingConfig;
+use crate::models::rejected::RejectedMessage;
 use crate::models::topic::Topic;
+use crate::models::{MULTI_FEATURE_NAME, check_quarantine_and_rate_limit, get_envelopes_with_format};
 
 pub mod mqtt_client;
 pub mod mqtt_config;
@@ -12,24 +19,151 @@ pub mod mqtt_options;
 
 const COMBINED_CA_FILES_PATH: &str = "./rootca_and_cert.pem";
 
-pub fn get_bytes_from_payload(msg: &Message) -> Vec<u8> {
-    let payload: String = get_string_payload(msg);
-    let topic: Topic = Topic::new(msg.topic());
-    debug!(target: "app", "get_bytes_from_payload - MQTT message topic = {}", &topic);
-    let msg_byte: Vec<u8> = get_msg_byte(&topic, &payload);
-    msg_byte
+// returns one result for each reading in the MQTT message,
+// encoded as messages for the main queue
+pub fn get_bytes_from_payload(msg: &Message, config: &ProcessingConfig) -> Vec<Result<Vec<u8>, MessageError>> {
+    get_envelopes_from_payload(msg, config)
+        .into_iter()
+        .map(|result| result.map(|envelope| envelope.encode(config.output_codec, config.schema_version)))
+        .collect()
 }
 
-fn get_string_payload(msg: &Message) -> String {
-    match std::str::from_utf8(msg.payload()) {
-        Ok(res) => {
-            debug!(target: "app", "get_string_payload - MQTT utf8 payload_str: {}", res);
-            res.to_string()
+// returns one result for each reading in the MQTT message
+pub fn get_envelopes_from_payload(msg: &Message, config: &ProcessingConfig) -> Vec<Result<Envelope, MessageError>> {
+    let received_at = Utc::now();
+    let (topic_str, format) = get_payload_format(msg);
+    let topic: Topic = match Topic::parse(topic_str, &config.topic_template) {
+        Ok(topic) => topic,
+        Err(err) => {
+            error!(target: "app", "get_envelopes_from_payload - invalid MQTT message topic, err = {}", err);
+            return vec![Err(MessageError::from(err))];
         }
+    };
+    debug!(target: "app", "get_envelopes_from_payload - MQTT message topic = {}, format = {}", &topic, format);
+    if let Err(err) = check_quarantine_and_rate_limit(&topic, received_at, config) {
+        return vec![Err(err)];
+    }
+    // oversize or overly complex payloads are rejected without parsing them
+    let results = match config.payload_limits.check(msg.payload(), format) {
+        Ok(()) => decode_payload(msg, &topic, format, received_at, config),
+        Err(err) => {
+            error!(target: "app", "get_envelopes_from_payload - payload of device {} exceeds limits, err = {}", &topic.device_id, err);
+            vec![Err(MessageError::from(err))]
+        }
+    };
+    // devices with too many invalid readings are quarantined. Errors are counted for the device of the topic,
+    // so with a device registry only readings with valid credentials count, otherwise anyone publishing
+    // garbage on the topic of a device could quarantine it
+    for result in &results {
+        if let Err(err) = result
+            && (config.devices.is_none()
+                || matches!(err, MessageError::Rejected { reason, .. } if reason.is_validation()))
+            && config.quarantine.record_error(&topic.device_id, received_at)
+        {
+            error!(target: "app", "get_envelopes_from_payload - device {} quarantined, last err = {}", &topic.device_id, err);
+            break;
+        }
+    }
+    results
+}
+
+// decodes a payload within the limits, in its format
+fn decode_payload(
+    msg: &Message,
+    topic: &Topic,
+    format: PayloadFormat,
+    received_at: DateTime<Utc>,
+    config: &ProcessingConfig,
+) -> Vec<Result<Envelope, MessageError>> {
+    match format {
+        PayloadFormat::Json => match get_string_payload(msg) {
+            Ok(payload) => {
+                debug!(target: "app", "decode_payload - MQTT utf8 payload_str: {}", config.redacted_fields.payload(payload));
+                get_envelopes_with_format(topic, payload.as_bytes(), format, received_at, config)
+            }
+            Err(err) => vec![Err(err)],
+        },
+        _ => get_envelopes_with_format(topic, msg.payload(), format, received_at, config),
+    }
+}
+
+// format of the payload, selected by topic suffix, MQTT v5 content-type or first byte.
+// Returns also the topic without the format suffix.
+fn get_payload_format(msg: &Message) -> (&str, PayloadFormat) {
+    let (topic_str, suffix_format) = PayloadFormat::split_topic(msg.topic());
+    let format = suffix_format
+        .or_else(|| {
+            msg.properties()
+                .get_string(PropertyCode::ContentType)
+                .and_then(|content_type| PayloadFormat::from_content_type(&content_type))
+        })
+        .unwrap_or_else(|| PayloadFormat::from_magic_byte(msg.payload()));
+    (topic_str, format)
+}
+
+// feature of the topic used as metrics label: a registered feature, `multi` or `unknown`,
+// so devices cannot create a new label with each topic
+pub fn get_feature_label(msg: &Message, config: &ProcessingConfig) -> String {
+    let (topic_str, _) = PayloadFormat::split_topic(msg.topic());
+    match Topic::parse(topic_str, &config.topic_template) {
+        Ok(topic) if topic.feature_name == MULTI_FEATURE_NAME || config.registry.get(&topic.feature_name).is_some() => {
+            topic.feature_name
+        }
+        _ => String::from("unknown"),
+    }
+}
+
+// device of the topic used as metrics label, so devices cannot create a new label with each deviceUuid.
+// With a device registry, only its devices have their own label, and spoofed ids are `other`.
+// Labels are capped anyway by `Metrics::device_label`.
+pub fn get_device_label(device_id: &str, config: &ProcessingConfig, metrics: &Metrics) -> String {
+    match &config.devices {
+        Some(devices) if !devices.contains(device_id) => String::from(OTHER_DEVICE_LABEL),
+        _ => metrics.device_label(&device_id.to_lowercase()),
+    }
+}
+
+// envelope for the rejects queue, with the feature of the reading or of the topic.
+// Secrets of the payload, like apiToken, are masked because the queue is readable by other services.
+pub fn get_rejected_bytes(msg: &Message, err: &MessageError, config: &ProcessingConfig) -> Vec<u8> {
+    let feature = match err {
+        MessageError::Rejected { feature, .. } => feature.clone(),
+        _ => Topic::new(PayloadFormat::split_topic(msg.topic()).0).feature_name,
+    };
+    let reason = match err {
+        MessageError::Rejected { reason, .. } => reason.to_string(),
+        _ => err.to_string(),
+    };
+    RejectedMessage::new_as_json(
+        msg.topic().to_string(),
+        feature,
+        err.kind().to_string(),
+        reason,
+        get_rejected_payload(msg, config),
+    )
+    .into_bytes()
+}
+
+// payload as redacted JSON text. Binary payloads are converted to JSON to redact them,
+// and they are dropped if they cannot be decoded, because their secrets cannot be found.
+fn get_rejected_payload(msg: &Message, config: &ProcessingConfig) -> String {
+    let json = match get_payload_format(msg).1 {
+        PayloadFormat::Json => String::from_utf8_lossy(msg.payload()).into_owned(),
+        format => match format.decode::<serde_json::Value>(msg.payload()) {
+            Ok(value) => value.to_string(),
+            Err(_) => return String::new(),
+        },
+    };
+    config.redacted_fields.redact_secrets(&json)
+}
+
+// validates the payload as utf8, borrowing it from the MQTT message without copying
+fn get_string_payload(msg: &Message) -> Result<&str, MessageError> {
+    match std::str::from_utf8(msg.payload()) {
+        Ok(res) => Ok(res),
         Err(err) => {
-            // this shouldn't happen, because payload in Message is a Vec<u8>
             error!(target: "app", "get_string_payload - Cannot read MQTT message payload as utf8. Error = {:?}", err);
-            "".to_string()
+            Err(MessageError::InvalidUtf8(err))
         }
     }
 }
@@ -37,9 +171,16 @@ fn get_string_payload(msg: &Message) -> String {
 #[cfg(test)]
 mod tests {
     use crate::config::init;
+    use crate::metrics::{MAX_DEVICE_LABELS, Metrics};
+    use crate::models::device_registry::ReloadableDeviceRegistry;
     use crate::models::get_msg_byte;
-    use crate::models::topic::Topic;
-    use crate::mqtt::get_bytes_from_payload;
+    use crate::models::payload_limits::PayloadLimits;
+    use crate::models::processing_config::ProcessingConfig;
+    use crate::models::quarantine::{ErrorRatePolicy, Quarantine};
+    use crate::models::topic::{Topic, TopicTemplate};
+    use crate::mqtt::{get_bytes_from_payload, get_device_label, get_rejected_bytes};
+    use crate::redact::RedactedFields;
+    use chrono::{DateTime, Utc};
     use paho_mqtt::Message;
     use pretty_assertions::assert_eq;
     use serde::Serialize;
@@ -54,6 +195,7 @@ mod tests {
         topic: &Topic,
     ) -> String {
         json!({
+            "schemaVersion": 2,
             "apiToken": api_token,
             "deviceUuid": device_uuid,
             "featureUuid": feature_uuid,
@@ -73,6 +215,7 @@ mod tests {
     fn ok_get_bytes_from_payload() {
         // init logger and env
         let _ = init();
+        let config = ProcessingConfig::default();
 
         // create a paho_mqtt::Message
         let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
@@ -90,15 +233,316 @@ mod tests {
             + r#"","payload":{"value":"#
             + value.to_string().as_str()
             + r#"}}"#;
-        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, msg_payload.as_str());
+        let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, msg_payload.as_str(), Utc::now(), &config).unwrap();
         let message = Message::new(format!("sensors/{}/{}", device_uuid, sensor_type), msg_byte_arr, 0);
 
         // call function get_bytes_from_payload
-        let bytes = get_bytes_from_payload(&message);
+        let results = get_bytes_from_payload(&message, &config);
+        assert_eq!(results.len(), 1);
+        let bytes = results[0].as_ref().unwrap();
 
-        // check result
-        let result = from_utf8(bytes.as_slice()).unwrap();
+        // check result, `receivedAt` is set by get_bytes_from_payload with the current time
+        let mut result: serde_json::Value = serde_json::from_str(from_utf8(bytes.as_slice()).unwrap()).unwrap();
+        let received_at = result.as_object_mut().unwrap().remove("receivedAt").unwrap();
+        assert!(received_at.as_str().unwrap().parse::<DateTime<Utc>>().is_ok());
         let expected_value = get_expected_json_string::<f64>(api_token, device_uuid, feature_uuid, value, &topic);
         assert_eq!(result.to_string(), expected_value);
     }
+
+    #[test]
+    fn ok_get_bytes_from_binary_payload() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let notification = json!({
+            "deviceUuid": device_uuid,
+            "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896",
+            "apiToken": "473a4861-632b-4915-b01e-cf1d418966c6",
+            "payload": {"value": 55.0}
+        });
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&notification, &mut cbor).unwrap();
+        let msgpack = rmp_serde::to_vec_named(&notification).unwrap();
+
+        // format selected by topic suffix and by first byte
+        for (topic, payload) in [
+            (format!("sensors/{}/humidity/cbor", device_uuid), cbor.clone()),
+            (format!("sensors/{}/humidity", device_uuid), cbor),
+            (format!("sensors/{}/humidity", device_uuid), msgpack),
+        ] {
+            let message = Message::new(topic, payload, 0);
+            let results = get_bytes_from_payload(&message, &config);
+            let result: serde_json::Value = serde_json::from_slice(results[0].as_ref().unwrap()).unwrap();
+            assert_eq!(result["topic"]["featureName"], "humidity");
+            assert_eq!(result["payload"]["value"], 55.0);
+        }
+    }
+
+    #[test]
+    fn wrong_get_bytes_from_payload() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig::default();
+
+        // JSON detected by first byte, but not valid UTF-8
+        let message = Message::new(
+            "sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature",
+            vec![b'{', 0xff],
+            0,
+        );
+        let results = get_bytes_from_payload(&message, &config);
+        let err = results[0].as_ref().err().unwrap();
+        assert_eq!(err.kind(), "invalid_utf8");
+
+        // rejects envelope with the kind of error and the feature of the topic
+        let rejected: serde_json::Value = serde_json::from_slice(&get_rejected_bytes(&message, err, &config)).unwrap();
+        assert_eq!(rejected["feature"], "temperature");
+        assert_eq!(rejected["kind"], "invalid_utf8");
+        assert_eq!(rejected["reason"], err.to_string());
+
+        // topic with more segments than the template
+        let message = Message::new("sensors/abc/x/y/temperature", "{}", 0);
+        let results = get_bytes_from_payload(&message, &config);
+        assert_eq!(
+            results[0].as_ref().err().unwrap().to_string(),
+            "Invalid topic error: topic sensors/abc/x/y/temperature doesn't match template sensors/{deviceId}/{featureName}"
+        );
+    }
+
+    #[test]
+    fn check_get_rejected_bytes_redacted() {
+        // init logger and env
+        let _ = init();
+        let api_token = "473a4861-632b-4915-b01e-cf1d418966c6";
+        // secrets are masked even if they are not masked in logs
+        let config = ProcessingConfig {
+            redacted_fields: RedactedFields::new(vec![]),
+            ..ProcessingConfig::default()
+        };
+        let notification = json!({
+            "deviceUuid": "246e3256-f0dd-4fcb-82c5-ee20c2267eeb",
+            "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896",
+            "apiToken": api_token,
+            "payload": {"value": 1000.0},
+            "nonce": "nonce-1",
+            "signature": "9f1c"
+        });
+        let msgpack = rmp_serde::to_vec_named(&notification).unwrap();
+        let topic = "sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/humidity";
+
+        for payload in [notification.to_string().into_bytes(), msgpack] {
+            let message = Message::new(topic, payload, 0);
+            let results = get_bytes_from_payload(&message, &config);
+            let err = results[0].as_ref().err().unwrap();
+            let bytes = get_rejected_bytes(&message, err, &config);
+            assert!(!from_utf8(&bytes).unwrap().contains(api_token));
+            let rejected: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(rejected["kind"], "above_max");
+            let payload: serde_json::Value = serde_json::from_str(rejected["payload"].as_str().unwrap()).unwrap();
+            assert_eq!(payload["apiToken"], "***");
+            assert_eq!(payload["nonce"], "***");
+            assert_eq!(payload["signature"], "***");
+            assert_eq!(payload["payload"]["value"], 1000.0);
+        }
+
+        // binary payloads that cannot be decoded are dropped
+        let mut truncated = rmp_serde::to_vec_named(&notification).unwrap();
+        truncated.truncate(80);
+        let message = Message::new(topic, truncated, 0);
+        let results = get_bytes_from_payload(&message, &config);
+        let bytes = get_rejected_bytes(&message, results[0].as_ref().err().unwrap(), &config);
+        let rejected: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(rejected["payload"], "");
+    }
+
+    #[test]
+    fn check_get_bytes_from_payload_quarantine() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig {
+            quarantine: Quarantine::new(ErrorRatePolicy {
+                threshold: 2,
+                ..ErrorRatePolicy::default()
+            }),
+            ..ProcessingConfig::default()
+        };
+        let topic = "sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature";
+        let valid = json!({
+            "deviceUuid": "246e3256-f0dd-4fcb-82c5-ee20c2267eeb",
+            "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896",
+            "apiToken": "473a4861-632b-4915-b01e-cf1d418966c6",
+            "payload": {"value": 21.5}
+        })
+        .to_string();
+
+        for _ in 0..2 {
+            let results = get_bytes_from_payload(&Message::new(topic, "{\"deviceUuid\":", 0), &config);
+            assert_eq!(results[0].as_ref().err().unwrap().kind(), "invalid_json");
+        }
+        // valid messages are dropped too, without decoding them
+        let results = get_bytes_from_payload(&Message::new(topic, valid.clone(), 0), &config);
+        assert_eq!(results[0].as_ref().err().unwrap().kind(), "quarantined");
+        let events = config.quarantine.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "error_rate");
+
+        assert!(
+            config
+                .quarantine
+                .release("246e3256-f0dd-4fcb-82c5-ee20c2267eeb", Utc::now())
+        );
+        let results = get_bytes_from_payload(&Message::new(topic, valid, 0), &config);
+        assert!(results[0].is_ok());
+    }
+
+    #[test]
+    fn check_get_bytes_from_payload_quarantine_device_registry() {
+        // init logger and env
+        let _ = init();
+        let path = std::env::temp_dir().join("check_get_bytes_from_payload_quarantine_device_registry.json");
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        // SHA-256 of the API token of the payload
+        std::fs::write(
+            &path,
+            json!({"devices": [{
+                "deviceUuid": device_uuid,
+                "apiTokenHash": "896c8db249671c4c68dff319d25dd859c4a0bde3efd1d201e66f61e2c9f9caa3",
+                "features": ["temperature"]
+            }]})
+            .to_string(),
+        )
+        .unwrap();
+        let config = ProcessingConfig {
+            devices: Some(ReloadableDeviceRegistry::load(path.to_str().unwrap()).unwrap()),
+            quarantine: Quarantine::new(ErrorRatePolicy {
+                threshold: 2,
+                ..ErrorRatePolicy::default()
+            }),
+            ..ProcessingConfig::default()
+        };
+        std::fs::remove_file(&path).unwrap();
+        let topic = "sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature";
+        let reading = |api_token: &str, value: f64| {
+            json!({
+                "deviceUuid": device_uuid,
+                "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896",
+                "apiToken": api_token,
+                "payload": {"value": value}
+            })
+            .to_string()
+        };
+
+        // garbage and wrong credentials on the topic of the device don't count
+        for _ in 0..2 {
+            get_bytes_from_payload(&Message::new(topic, "{\"deviceUuid\":", 0), &config);
+            let results = get_bytes_from_payload(&Message::new(topic, reading("stolen", 1000.0), 0), &config);
+            assert_eq!(results[0].as_ref().err().unwrap().kind(), "invalid_token");
+        }
+        assert!(!config.quarantine.is_quarantined(device_uuid, Utc::now()));
+
+        // invalid readings of the device do
+        for _ in 0..2 {
+            let results = get_bytes_from_payload(
+                &Message::new(topic, reading("473a4861-632b-4915-b01e-cf1d418966c6", 1000.0), 0),
+                &config,
+            );
+            assert_eq!(results[0].as_ref().err().unwrap().kind(), "above_max");
+        }
+        assert!(config.quarantine.is_quarantined(device_uuid, Utc::now()));
+    }
+
+    #[test]
+    fn check_get_bytes_from_payload_limits() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig {
+            payload_limits: PayloadLimits {
+                max_bytes: 256,
+                max_depth: 4,
+                ..PayloadLimits::default()
+            },
+            ..ProcessingConfig::default()
+        };
+        let topic = "sensors/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature";
+        let results = get_bytes_from_payload(&Message::new(topic, "[".repeat(257), 0), &config);
+        assert_eq!(
+            results[0].as_ref().err().unwrap().to_string(),
+            "Payload limit exceeded error: payload of 257 bytes exceeds max 256"
+        );
+        // rejected before the parser, which reports a type mismatch within the limits
+        let results = get_bytes_from_payload(&Message::new(topic, "[".repeat(5), 0), &config);
+        assert_eq!(results[0].as_ref().err().unwrap().kind(), "limit_exceeded");
+        let results = get_bytes_from_payload(&Message::new(topic, "[".repeat(4), 0), &config);
+        assert_eq!(results[0].as_ref().err().unwrap().kind(), "type_mismatch");
+    }
+
+    #[test]
+    fn ok_get_bytes_from_payload_topic_template() {
+        // init logger and env
+        let _ = init();
+        let config = ProcessingConfig {
+            topic_template: TopicTemplate::new("home/{homeId}/{room}/{deviceId}/{featureName}").unwrap(),
+            ..ProcessingConfig::default()
+        };
+
+        let payload = json!({
+            "deviceUuid": "246e3256-f0dd-4fcb-82c5-ee20c2267eeb",
+            "featureUuid": "41cb3f47-894c-45e9-90d9-a4d4de903896",
+            "apiToken": "473a4861-632b-4915-b01e-cf1d418966c6",
+            "payload": {"value": 21.5}
+        });
+        let message = Message::new(
+            "home/h1/kitchen/246e3256-f0dd-4fcb-82c5-ee20c2267eeb/temperature",
+            payload.to_string(),
+            0,
+        );
+        let results = get_bytes_from_payload(&message, &config);
+        let result: serde_json::Value = serde_json::from_slice(results[0].as_ref().unwrap()).unwrap();
+        assert_eq!(
+            result["topic"],
+            json!({
+                "family": "home",
+                "deviceId": "246e3256-f0dd-4fcb-82c5-ee20c2267eeb",
+                "featureName": "temperature",
+                "segments": {"homeId": "h1", "room": "kitchen"}
+            })
+        );
+    }
+
+    #[test]
+    fn check_get_device_label() {
+        let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
+        let path = std::env::temp_dir().join("check_get_device_label.json");
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{"devices": [{{"deviceUuid": "{}", "apiTokenHash": "{}", "features": []}}]}}"#,
+                device_uuid,
+                "0".repeat(64)
+            ),
+        )
+        .unwrap();
+        let config = ProcessingConfig {
+            devices: Some(ReloadableDeviceRegistry::load(path.to_str().unwrap()).unwrap()),
+            ..ProcessingConfig::default()
+        };
+        std::fs::remove_file(&path).unwrap();
+
+        let metrics = Metrics::default();
+        assert_eq!(
+            get_device_label(&device_uuid.to_uppercase(), &config, &metrics),
+            device_uuid
+        );
+        assert_eq!(get_device_label("random-device", &config, &metrics), "other");
+        // without device registry, devices are counted one by one up to a limit
+        let config = ProcessingConfig::default();
+        assert_eq!(get_device_label("Random-Device", &config, &metrics), "random-device");
+        for i in 0..MAX_DEVICE_LABELS {
+            get_device_label(&format!("device-{}", i), &config, &metrics);
+        }
+        assert_eq!(get_device_label("device-new", &config, &metrics), "other");
+        assert_eq!(get_device_label(device_uuid, &config, &metrics), device_uuid);
+    }
 }
@@ -1,15 +1,25 @@
 use std::string::String;
 use std::time::Duration;
 
+use chrono::Utc;
 use futures::stream::StreamExt;
 use paho_mqtt::{AsyncClient, AsyncReceiver, ConnectOptions, Message, ServerResponse};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::mqtt::mqtt_config::MqttConfig;
 use crate::mqtt::mqtt_options::MqttOptions;
 
 pub struct MqttClient {
     conn_opts: ConnectOptions,
     client: AsyncClient,
+    // config used to rebuild `conn_opts` on reconnection, see `set_reload_config`
+    reload_config: Option<MqttConfig>,
+    // reconnections and messages in the stream buffer, see `set_metrics`
+    metrics: Option<Metrics>,
+    // connection state reported to /healthz and /readyz, see `set_health_check`
+    health: Option<Health>,
     pub message_stream: AsyncReceiver<Option<Message>>,
 }
 
@@ -21,6 +31,9 @@ impl MqttClient {
         Ok(Self {
             conn_opts: options.conn_opts,
             client,
+            reload_config: None,
+            metrics: None,
+            health: None,
             message_stream,
         })
     }
@@ -32,21 +45,67 @@ impl MqttClient {
             tokio::time::sleep(Duration::from_millis(30000)).await;
         }
         info!(target: "app", "connect - MQTT Connection succeeded");
+        self.set_health(true);
     }
 
-    pub async fn reconnect(&self) -> paho_mqtt::Result<ServerResponse> {
+    pub fn set_health_check(&mut self, health: Health) {
+        self.health = Some(health);
+    }
+
+    fn set_health(&self, connected: bool) {
+        if let Some(health) = &self.health {
+            health.set_mqtt_connected(connected, Utc::now());
+        }
+    }
+
+    // on reconnection, the password file and the certificates of the config are read again,
+    // so rotated credentials are used without restarting the service
+    pub fn set_reload_config(&mut self, mqtt_config: MqttConfig) {
+        self.reload_config = Some(mqtt_config);
+    }
+
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    pub async fn reconnect(&mut self) -> paho_mqtt::Result<ServerResponse> {
         info!(target: "app", "reconnect - Reconnecting to the MQTT server...");
-        self.client.reconnect().await
+        if let Some(metrics) = &self.metrics {
+            metrics.inc_mqtt_reconnect();
+        }
+        let result = if self.reload_config.is_some() {
+            self.reload_conn_opts();
+            self.client.connect(self.conn_opts.clone()).await
+        } else {
+            self.client.reconnect().await
+        };
+        self.set_health(result.is_ok());
+        result
     }
 
-    pub async fn subscribe(&mut self, topics_list: &[&str]) -> Result<(), paho_mqtt::Error> {
-        info!(target: "app", "subscribe - Subscribing to the topics: {:?}", topics_list);
+    // on errors, the previous options are used
+    fn reload_conn_opts(&mut self) {
+        let Some(mqtt_config) = self.reload_config.as_mut() else {
+            return;
+        };
+        match mqtt_config.reload_secrets() {
+            Ok(()) => match MqttOptions::connect_options(mqtt_config) {
+                Ok(conn_opts) => self.conn_opts = conn_opts,
+                Err(err) => {
+                    warn!(target: "app", "reload_conn_opts - cannot rebuild ConnectOptions, using the previous ones. Err = {:?}", err)
+                }
+            },
+            Err(err) => {
+                warn!(target: "app", "reload_conn_opts - cannot reload MQTT secrets, using the previous ones. Err = {:?}", err)
+            }
+        }
+    }
 
-        let topics: Vec<String> = topics_list.iter().map(|s| s.to_string()).collect();
+    pub async fn subscribe(&mut self, topics: &[String]) -> Result<(), paho_mqtt::Error> {
         info!(target: "app", "subscribe - Subscribing to MQTT topics: {:?}", topics);
         let qos = vec![0; topics.len()];
         // We subscribe to the topic(s) we want here.
-        match self.client.subscribe_many(&topics, &qos).await {
+        match self.client.subscribe_many(topics, &qos).await {
             Ok(_) => {
                 info!(target: "app", "subscribe - Subscription to the topics completed");
                 Ok(())
@@ -59,7 +118,15 @@ impl MqttClient {
     }
 
     pub async fn get_next_message(&mut self) -> Option<Option<Message>> {
-        self.message_stream.next().await
+        let message = self.message_stream.next().await;
+        // None means the connection was lost
+        if let Some(None) = &message {
+            self.set_health(false);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.set_mqtt_buffered(self.message_stream.len());
+        }
+        message
     }
 
     pub async fn disconnect(&mut self) -> paho_mqtt::Result<ServerResponse> {
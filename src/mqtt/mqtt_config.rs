@@ -1,8 +1,13 @@
+use std::fmt;
 use std::string::String;
 
 use crate::config::Env;
+use crate::config::secret::read_secret_file;
+use crate::errors::secret_error::SecretError;
 use crate::mqtt::COMBINED_CA_FILES_PATH;
+use crate::redact::mask;
 
+// Debug and Display are implemented by hand to mask the password
 pub struct MqttConfig {
     pub url: String,
     pub port: u16,
@@ -10,6 +15,8 @@ pub struct MqttConfig {
     pub auth: bool,
     pub user: String,
     pub password: String,
+    // optional file with the password, read again on reconnection
+    pub password_file: String,
     pub tls: bool,
     pub root_ca_file: String,
     pub cert_file: String,
@@ -26,6 +33,7 @@ impl MqttConfig {
             auth: env.mqtt_auth,
             user: env.mqtt_user.clone(),
             password: env.mqtt_password.clone(),
+            password_file: env.mqtt_password_file.clone(),
             tls: env.mqtt_tls,
             root_ca_file: env.root_ca.clone(),
             cert_file: env.mqtt_cert_file.clone(),
@@ -33,4 +41,44 @@ impl MqttConfig {
             ca_files_path: COMBINED_CA_FILES_PATH.to_string(),
         }
     }
+
+    // reads the password file again, to follow the rotation of credentials.
+    // Certificates are read from their files every time the connect options are built.
+    pub fn reload_secrets(&mut self) -> Result<(), SecretError> {
+        if !self.password_file.is_empty() {
+            self.password = read_secret_file(&self.password_file)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MqttConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MqttConfig")
+            .field("url", &self.url)
+            .field("port", &self.port)
+            .field("client_id", &self.client_id)
+            .field("auth", &self.auth)
+            .field("user", &self.user)
+            .field("password", &mask(&self.password))
+            .field("password_file", &self.password_file)
+            .field("tls", &self.tls)
+            .field("root_ca_file", &self.root_ca_file)
+            .field("cert_file", &self.cert_file)
+            .field("key_file", &self.key_file)
+            .field("ca_files_path", &self.ca_files_path)
+            .finish()
+    }
+}
+
+impl fmt::Display for MqttConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = if self.tls { "mqtts" } else { "mqtt" };
+        if self.auth {
+            write!(fmt, "{}://{}:{}@", scheme, self.user, mask(&self.password))?;
+        } else {
+            write!(fmt, "{}://", scheme)?;
+        }
+        write!(fmt, "{}:{} (client id {})", self.url, self.port, self.client_id)
+    }
 }
@@ -27,24 +27,38 @@ impl MqttOptions {
         };
         info!(target: "app", "mqtt_uri = {}", &mqtt_uri);
 
+        let create_options = CreateOptionsBuilder::new()
+            .server_uri(mqtt_uri)
+            .client_id(&mqtt_config.client_id)
+            .finalize();
+
+        let conn_opts_result = Self::connect_options(mqtt_config);
+        if let Err(err) = conn_opts_result {
+            error!(target: "app", "cannot instantiate MqttOptions, err = {:?}", err);
+            panic!("cannot instantiate MqttOptions!");
+        }
+
+        Self {
+            create_opts: create_options,
+            conn_opts: conn_opts_result.unwrap(),
+        }
+    }
+
+    // ConnectOptions with the current credentials and certificates.
+    // Called again on reconnection, so rotated files are used.
+    pub fn connect_options(mqtt_config: &MqttConfig) -> Result<ConnectOptions, anyhow::Error> {
         // Create CA file in 'COMBINED_CA_FILES_PATH' merging 'root_ca' and 'mqtt_cert_file',
         // otherwise, paho.mqtt.rust won't be able to connect.
         if mqtt_config.tls {
             info!(target: "app", "Preparing MQTT CA file");
-            let merge_result = Self::merge_ca_files(&mqtt_config.root_ca_file, &mqtt_config.cert_file);
-            if let Err(err) = merge_result {
+            if let Err(err) = Self::merge_ca_files(&mqtt_config.root_ca_file, &mqtt_config.cert_file) {
                 error!(target: "app", "cannot merge MQTT CA files, err = {:?}", err);
-                panic!("cannot merge MQTT CA files");
+                return Err(err);
             }
         }
 
-        let create_options = CreateOptionsBuilder::new()
-            .server_uri(mqtt_uri)
-            .client_id(&mqtt_config.client_id)
-            .finalize();
-
         info!(target: "app", "Creating MQTT ConnectOptions...");
-        let conn_opts_result = Self::build_connect_options(
+        Self::build_connect_options(
             &mqtt_config.auth,
             &mqtt_config.user,
             &mqtt_config.password,
@@ -52,17 +66,7 @@ impl MqttOptions {
             &mqtt_config.cert_file,
             &mqtt_config.key_file,
             &mqtt_config.ca_files_path,
-        );
-
-        if let Err(err) = conn_opts_result {
-            error!(target: "app", "cannot instantiate MqttOptions, err = {:?}", err);
-            panic!("cannot instantiate MqttOptions!");
-        }
-
-        Self {
-            create_opts: create_options,
-            conn_opts: conn_opts_result.unwrap(),
-        }
+        )
     }
 
     fn merge_ca_files(root_ca: &String, mqtt_cert_file: &String) -> Result<(), anyhow::Error> {
@@ -1,5 +1,6 @@
 use std::process::Command;
 
+use chrono::Utc;
 use paho_mqtt::Message;
 use pretty_assertions::assert_eq;
 use tracing::{debug, error};
@@ -7,19 +8,22 @@ use tracing::{debug, error};
 use producer::amqp::AmqpClient;
 use producer::config::{Env, init};
 use producer::errors::message_error::MessageError;
+use producer::metrics::Metrics;
 use producer::models::get_msg_byte;
+use producer::models::processing_config::ProcessingConfig;
 use producer::models::topic::Topic;
 use producer::mqtt::mqtt_client::MqttClient;
 use producer::mqtt::mqtt_config::MqttConfig;
 use producer::mqtt::mqtt_options::MqttOptions;
 
-use crate::{TOPICS, process_mqtt_message};
+use crate::process_mqtt_message;
 
 #[tokio::test]
 #[test_log::test]
 async fn receive_message_via_mqtt() {
     // init logger and env variables
     let env: Env = init();
+    let processing_config = ProcessingConfig::default();
 
     // init MQTT client
     let mqtt_config: MqttConfig = MqttConfig::new(&env);
@@ -27,9 +31,9 @@ async fn receive_message_via_mqtt() {
         Ok(mut mqtt_client) => {
             // connect to MQTT server and subscribe to topics
             mqtt_client.connect().await;
-            if let Err(err) = mqtt_client.subscribe(TOPICS).await {
-                error!(target: "app", "MQTT cannot subscribe to TOPICS, err = {:?}", err);
-                panic!("unknown error, because MQTT cannot subscribe to TOPICS");
+            if let Err(err) = mqtt_client.subscribe(&processing_config.topics()).await {
+                error!(target: "app", "MQTT cannot subscribe to topics, err = {:?}", err);
+                panic!("unknown error, because MQTT cannot subscribe to topics");
             }
             // create MQTT message payload
             let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
@@ -57,7 +61,7 @@ async fn receive_message_via_mqtt() {
                 .arg(&msg_payload_str)
                 .arg("-t")
                 .arg(format!("sensors/{}/{}", device_uuid, sensor_type))
-                .spawn()
+                .status()
                 .expect("command failed to start");
 
             // receive MQTT message
@@ -81,6 +85,8 @@ async fn receive_message_via_mqtt() {
 async fn send_mqtt_message_via_amqp() {
     // init logger and env variables
     let env: Env = init();
+    let processing_config = ProcessingConfig::default();
+    let metrics = Metrics::default();
 
     // init AMQP client
     let mut amqp_client = AmqpClient::new(env.amqp_uri.clone(), env.amqp_queue_name.clone());
@@ -92,9 +98,9 @@ async fn send_mqtt_message_via_amqp() {
         Ok(mut mqtt_client) => {
             // connect to MQTT server and subscribe to topics
             mqtt_client.connect().await;
-            if let Err(err) = mqtt_client.subscribe(TOPICS).await {
-                error!(target: "app", "MQTT cannot subscribe to TOPICS, err = {:?}", err);
-                panic!("unknown error, because MQTT cannot subscribe to TOPICS");
+            if let Err(err) = mqtt_client.subscribe(&processing_config.topics()).await {
+                error!(target: "app", "MQTT cannot subscribe to topics, err = {:?}", err);
+                panic!("unknown error, because MQTT cannot subscribe to topics");
             }
             // create MQTT message payload
             let device_uuid = "246e3256-f0dd-4fcb-82c5-ee20c2267eeb";
@@ -112,11 +118,20 @@ async fn send_mqtt_message_via_amqp() {
                 + value.to_string().as_str()
                 + r#"}}"#;
             let topic: Topic = Topic::new(format!("sensors/{}/{}", device_uuid, sensor_type).as_str());
-            let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, msg_payload_str.as_str());
+            let msg_byte_arr: Vec<u8> =
+                get_msg_byte(&topic, msg_payload_str.as_str(), Utc::now(), &processing_config).unwrap_or_default();
             let message = Message::new(format!("sensors/{}/{}", device_uuid, sensor_type), msg_byte_arr, 0);
 
             // send MQTT message via AMQP
-            let result = process_mqtt_message(&Some(message), &mut mqtt_client, &mut amqp_client).await;
+            let result = process_mqtt_message(
+                &Some(message),
+                &mut Vec::new(),
+                &mut mqtt_client,
+                &mut amqp_client,
+                &processing_config,
+                &metrics,
+            )
+            .await;
 
             // check result: it should return () if `process_mqtt_message`
             // successfully sent the message via AMQP
@@ -134,6 +149,8 @@ async fn send_mqtt_message_via_amqp() {
 async fn wrong_sensor_type_for_process_mqtt_message() {
     // init logger and env variables
     let env: Env = init();
+    let processing_config = ProcessingConfig::default();
+    let metrics = Metrics::default();
 
     // create an instance of AMQP client
     let mut amqp_client = AmqpClient::new(env.amqp_uri.clone(), env.amqp_queue_name.clone());
@@ -157,17 +174,26 @@ async fn wrong_sensor_type_for_process_mqtt_message() {
         + value.to_string().as_str()
         + r#"}}"#;
     let topic: Topic = Topic::new(format!("sensors/{}/{}", device_uuid, sensor_type).as_str());
-    let msg_byte_arr: Vec<u8> = get_msg_byte(&topic, msg_payload_str.as_str());
+    let msg_byte_arr: Vec<u8> =
+        get_msg_byte(&topic, msg_payload_str.as_str(), Utc::now(), &processing_config).unwrap_or_default();
     let message = Message::new(format!("sensors/{}/{}", device_uuid, sensor_type), msg_byte_arr, 0);
 
     // invoke `process_mqtt_message` with the bad MQTT message
-    let result = process_mqtt_message(&Some(message), &mut mqtt_client, &mut amqp_client).await;
-
-    // check result: it should return MessageError::EmptyMessageError,
+    let result = process_mqtt_message(
+        &Some(message),
+        &mut Vec::new(),
+        &mut mqtt_client,
+        &mut amqp_client,
+        &processing_config,
+        &metrics,
+    )
+    .await;
+
+    // check result: it should return MessageError::UnknownFeature,
     // because sensor_type is unknown
     assert_eq!(
         result.err().unwrap().to_string(),
-        anyhow::Error::from(MessageError::EmptyMessageError).to_string()
+        anyhow::Error::from(MessageError::UnknownFeature(String::from(sensor_type))).to_string()
     );
 }
 
@@ -176,6 +202,8 @@ async fn wrong_sensor_type_for_process_mqtt_message() {
 async fn reconnect_to_mqtt_on_message() {
     // init logger and env variables
     let env: Env = init();
+    let processing_config = ProcessingConfig::default();
+    let metrics = Metrics::default();
 
     // init AMQP client
     let mut amqp_client = AmqpClient::new(env.amqp_uri.clone(), env.amqp_queue_name.clone());
@@ -187,16 +215,24 @@ async fn reconnect_to_mqtt_on_message() {
         Ok(mut mqtt_client) => {
             // connect to MQTT server and subscribe to topics
             mqtt_client.connect().await;
-            if let Err(err) = mqtt_client.subscribe(TOPICS).await {
-                error!(target: "app", "MQTT cannot subscribe to TOPICS, err = {:?}", err);
-                panic!("unknown error, because MQTT cannot subscribe to TOPICS");
+            if let Err(err) = mqtt_client.subscribe(&processing_config.topics()).await {
+                error!(target: "app", "MQTT cannot subscribe to topics, err = {:?}", err);
+                panic!("unknown error, because MQTT cannot subscribe to topics");
             }
             // disconnect from MQTT server
             let _ = mqtt_client.disconnect().await;
 
             // send MQTT message via AMQP
             // this will automatically trigger a `reconnect()`
-            let result = process_mqtt_message(&None, &mut mqtt_client, &mut amqp_client).await;
+            let result = process_mqtt_message(
+                &None,
+                &mut Vec::new(),
+                &mut mqtt_client,
+                &mut amqp_client,
+                &processing_config,
+                &metrics,
+            )
+            .await;
 
             // check result: it should return () if `process_mqtt_message`
             // successfully reconnected to MQTT server